@@ -1,8 +1,13 @@
+use crate::{limit::ConcurrencyLimit, GuardInfo};
 use axum::{
+    extract::{FromRequest, FromRequestParts, Request},
     handler::Handler,
+    http::{Method, StatusCode},
+    response::IntoResponse,
     routing::{MethodFilter, MethodRouter},
 };
-use std::{convert::Infallible, vec};
+use std::{convert::Infallible, fmt, future::Future, sync::Arc, vec};
+use tower::Service;
 
 macro_rules! top_level_acion_fn {
     (
@@ -30,13 +35,14 @@ macro_rules! top_level_acion_fn {
         $name:ident, $method:ident
     ) => {
         $(#[$m])+
-        pub fn $name<H, T, S>(name:&str, handler: H) -> Action<S>
+        #[track_caller]
+        pub fn $name<H, T, S>(name: impl AsRef<str>, handler: H) -> Action<S>
         where
             H: Handler<T, S>,
             T: 'static,
             S: Clone + Send + Sync + 'static,
         {
-            on(MethodFilter::$method, name, handler)
+            on(MethodFilter::$method, name.as_ref(), handler)
         }
     };
 }
@@ -67,16 +73,102 @@ macro_rules! chained_handler_fn {
     ) => {
         $(#[$m])+
         #[track_caller]
-        pub fn $name<H, T>(self, name: &str, handler: H) -> Self
+        pub fn $name<H, T>(self, name: impl AsRef<str>, handler: H) -> Self
         where
             H: Handler<T, S>,
             T: 'static,
             S: Send + Sync + 'static,
         {
-            self.on(MethodFilter::$method, name, handler)
+            self.on(MethodFilter::$method, name.as_ref(), handler)
+        }
+    };
+}
+macro_rules! top_level_service_fn {
+    (
+        $name:ident, $method:ident
+    ) => {
+        #[doc = concat!("Route `", stringify!($method) ,"` requests to the given service.")]
+        #[track_caller]
+        pub fn $name<T, S>(name: impl AsRef<str>, service: T) -> Action<S>
+        where
+            T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+            T::Response: IntoResponse + 'static,
+            T::Future: Send + 'static,
+            S: Clone + Send + Sync + 'static,
+        {
+            Action::new().on_service(MethodFilter::$method, name.as_ref(), service)
+        }
+    };
+}
+
+macro_rules! chained_service_fn {
+    (
+        $name:ident, $method:ident
+    ) => {
+        #[doc = concat!("Chain an additional service that will only accept `", stringify!($method),"` requests.")]
+        #[track_caller]
+        pub fn $name<T>(self, name: impl AsRef<str>, service: T) -> Self
+        where
+            T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+            T::Response: IntoResponse + 'static,
+            T::Future: Send + 'static,
+            S: Send + Sync + 'static,
+        {
+            self.on_service(MethodFilter::$method, name.as_ref(), service)
         }
     };
 }
+
+macro_rules! top_level_action_with_roles_fn {
+    (
+        $name:ident, $method:ident
+    ) => {
+        #[doc = concat!("Route `", stringify!($method) ,"` requests to the given handler with the action roles.")]
+        #[track_caller]
+        pub fn $name<H, T, S>(name: impl AsRef<str>, roles: &[String], handler: H) -> Action<S>
+        where
+            H: Handler<T, S>,
+            T: 'static,
+            S: Clone + Send + Sync + 'static,
+        {
+            Action::new().on_with_roles(MethodFilter::$method, name.as_ref(), Some(roles), handler)
+        }
+    };
+}
+
+macro_rules! chained_handler_with_roles_fn {
+    (
+        $name:ident, $method:ident
+    ) => {
+        #[doc = concat!("Chain an additional handler that will only accept `", stringify!($method),"` requests with the action roles.")]
+        #[track_caller]
+        pub fn $name<H, T>(self, name: impl AsRef<str>, roles: &[String], handler: H) -> Self
+        where
+            H: Handler<T, S>,
+            T: 'static,
+            S: Send + Sync + 'static,
+        {
+            self.on_with_roles(MethodFilter::$method, name.as_ref(), Some(roles), handler)
+        }
+    };
+}
+
+/// An action entry with its name, roles and method router
+#[derive(Clone)]
+pub(crate) struct ActionRoute<S, E> {
+    pub(crate) name: String,
+    pub(crate) roles: Option<Vec<String>>,
+    pub(crate) csp: Option<String>,
+    pub(crate) limit: Option<Arc<ConcurrencyLimit>>,
+    /// The methods of the router, unknown for the given method routers
+    pub(crate) filter: Option<MethodFilter>,
+    /// The candidate actions the guard picks from, empty for a single action
+    pub(crate) candidates: Vec<String>,
+    /// The router handles every method with its fallback, like `Action::any`
+    pub(crate) any: bool,
+    pub(crate) router: MethodRouter<S, E>,
+}
+
 /// create an action router with action name
 /// ```rust, ignore
 ///  use axum_guard_router::{action, GuardRouter};
@@ -86,7 +178,156 @@ macro_rules! chained_handler_fn {
 #[must_use]
 #[derive(Clone)]
 pub struct Action<S = (), E = Infallible> {
-    routers: Vec<(String, MethodRouter<S, E>)>,
+    routers: Vec<ActionRoute<S, E>>,
+}
+
+impl<S, E> Action<S, E>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Create an action with a method router, which can be fallible
+    ///
+    /// When the router is added to a guard router, its errors are logged and
+    /// responded with `500 Internal Server Error`.
+    ///
+    /// ```rust, ignore
+    ///  let method_router = MethodRouter::new().get_service(service_fn(|_| async {
+    ///      Err::<Response, _>(io::Error::other("disk failure"))
+    ///  }));
+    ///  let router = GuardRouter::new("my:router:resource", Arc::new(MyGuard))
+    ///     .route("/file", Action::from_method_router("my:read", method_router));
+    /// ```
+    #[track_caller]
+    pub fn from_method_router(name: impl AsRef<str>, method_router: MethodRouter<S, E>) -> Self {
+        Self::create(name.as_ref(), None, method_router)
+    }
+
+    #[track_caller]
+    pub(crate) fn create(
+        name: &str,
+        roles: Option<&[String]>,
+        method_router: MethodRouter<S, E>,
+    ) -> Self {
+        validate_name("action", name);
+        Self {
+            routers: vec![ActionRoute {
+                name: name.to_string(),
+                roles: roles.map(|roles| roles.to_vec()),
+                csp: None,
+                limit: None,
+                filter: None,
+                candidates: Vec::new(),
+                any: false,
+                router: method_router,
+            }],
+        }
+    }
+
+    /// Merge the actions of another action builder, like the read and the
+    /// write actions of a path built in different modules
+    ///
+    /// # Panics
+    ///
+    /// Panics if both builders have an action of the same method, or both
+    /// have an action of any method. The methods of the method routers are
+    /// unknown, like in `GuardRouter::manifest`, so their conflicts panic
+    /// when the router is built.
+    ///
+    /// ```rust, ignore
+    ///  let read = action::get("my:get", handler1);
+    ///  let write = action::post("my:create", handler2).put("my:update", handler3);
+    ///  let router = GuardRouter::new("my:router:resource", Arc::new(MyGuard))
+    ///     .route("/user", read.merge(write));
+    /// ```
+    #[track_caller]
+    pub fn merge(mut self, other: Action<S, E>) -> Self {
+        let methods: Vec<Method> = self
+            .routers
+            .iter()
+            .filter_map(|route| route.filter)
+            .flat_map(filter_methods)
+            .collect();
+        let conflict = other
+            .routers
+            .iter()
+            .filter_map(|route| route.filter)
+            .flat_map(filter_methods)
+            .find(|method| methods.contains(method));
+        if let Some(method) = conflict {
+            panic!("Cannot merge actions with the same method {method}");
+        }
+        let any = |action: &Self| action.routers.iter().any(|route| route.any);
+        assert!(
+            !(any(&self) && any(&other)),
+            "Cannot merge actions both accepting any method"
+        );
+        self.routers.extend(other.routers);
+        self
+    }
+
+    /// Set the roles of the actions added before, which have no roles yet
+    ///
+    /// The action roles replace the router roles.
+    ///
+    /// ```rust, ignore
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .route(
+    ///         "/user",
+    ///         action::delete("my:delete", delete)
+    ///             .roles(&["admin".to_string()])
+    ///             .get("my:get", get)
+    ///             .roles(&["viewer".to_string()]),
+    ///     );
+    /// ```
+    pub fn roles(self, roles: &[String]) -> Self {
+        self.default_roles(roles)
+    }
+
+    /// Set the roles of the actions without roles
+    pub(crate) fn default_roles(mut self, roles: &[String]) -> Self {
+        for route in self
+            .routers
+            .iter_mut()
+            .filter(|route| route.roles.is_none())
+        {
+            route.roles = Some(roles.to_vec());
+        }
+        self
+    }
+
+    /// Respond the errors of the method routers with 500
+    pub(crate) fn handle_error(self) -> Action<S, Infallible>
+    where
+        E: fmt::Display + Send + 'static,
+    {
+        let routers = self
+            .routers
+            .into_iter()
+            .map(|route| ActionRoute {
+                name: route.name,
+                roles: route.roles,
+                csp: route.csp,
+                limit: route.limit,
+                filter: route.filter,
+                candidates: route.candidates,
+                any: route.any,
+                router: route.router.handle_error(|err: E| async move {
+                    log::error!("Action: the service failed: {err}");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }),
+            })
+            .collect();
+        Action { routers }
+    }
+}
+
+impl<S> Default for Action<S, Infallible>
+where
+    S: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<S> Action<S, Infallible>
@@ -97,26 +338,89 @@ where
         Self { routers: vec![] }
     }
 
+    /// Chain an additional handler that will accept the requests of the methods
+    /// in the filter, like `axum::routing::MethodRouter::on`
+    ///
+    /// A combined filter, like `MethodFilter::GET.or(MethodFilter::HEAD)`, is
+    /// one guarded entry with one action for all of its methods.
     #[track_caller]
-    pub(crate) fn on<H, T>(mut self, filter: MethodFilter, name: &str, handler: H) -> Self
+    pub fn on<H, T>(self, filter: MethodFilter, name: impl AsRef<str>, handler: H) -> Self
     where
         H: Handler<T, S>,
         T: 'static,
         S: Send + Sync + 'static,
     {
-        self.routers
-            .push((name.to_string(), MethodRouter::new().on(filter, handler)));
+        self.on_with_roles(filter, name.as_ref(), None, handler)
+    }
+
+    #[track_caller]
+    pub(crate) fn on_with_roles<H, T>(
+        mut self,
+        filter: MethodFilter,
+        name: &str,
+        roles: Option<&[String]>,
+        handler: H,
+    ) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        validate_name("action", name);
+        self.routers.push(ActionRoute {
+            name: name.to_string(),
+            roles: roles.map(|roles| roles.to_vec()),
+            csp: None,
+            limit: None,
+            filter: Some(filter),
+            candidates: Vec::new(),
+            any: false,
+            router: MethodRouter::new().on(filter, handler),
+        });
         self
     }
 
-    pub(crate) fn routers(&self) -> Vec<(String, MethodRouter<S>)> {
-        self.routers.clone()
+    /// Set the `Content-Security-Policy` header of the `text/html` responses
+    /// for the actions added before, which have no csp yet
+    ///
+    /// ```rust, ignore
+    ///  let router = GuardRouter::new("my:router:resource", Arc::new(MyGuard))
+    ///     .route("/page", action::get("my:page", page).csp("default-src 'self'"));
+    /// ```
+    pub fn csp(mut self, csp: &str) -> Self {
+        for route in self.routers.iter_mut().filter(|route| route.csp.is_none()) {
+            route.csp = Some(csp.to_string());
+        }
+        self
     }
 
-    pub(crate) fn create(name: &str, method_router: MethodRouter<S>) -> Self {
-        Self {
-            routers: vec![(name.to_string(), method_router)],
+    /// Let the guard pick the authorized action among the candidates
+    #[track_caller]
+    pub(crate) fn candidates(mut self, candidates: &[String]) -> Self {
+        for candidate in candidates {
+            validate_name("action", candidate);
         }
+        for route in self.routers.iter_mut() {
+            route.candidates = candidates.to_vec();
+        }
+        self
+    }
+
+    /// Limit the concurrent requests of all the actions together
+    pub(crate) fn max_concurrent(mut self, max: usize) -> Self {
+        let limit = Arc::new(ConcurrencyLimit::new(max));
+        for route in self.routers.iter_mut() {
+            route.limit = Some(limit.clone());
+        }
+        self
+    }
+
+    pub(crate) fn routes(&self) -> &[ActionRoute<S, Infallible>] {
+        &self.routers
+    }
+
+    pub(crate) fn into_routers(self) -> Vec<ActionRoute<S, Infallible>> {
+        self.routers
     }
 
     chained_handler_fn!(delete, DELETE);
@@ -127,6 +431,113 @@ where
     chained_handler_fn!(post, POST);
     chained_handler_fn!(put, PUT);
     chained_handler_fn!(trace, TRACE);
+
+    /// Chain an additional handler that will accept the requests of any method
+    ///
+    /// The handler is the method fallback of the path, the method specific
+    /// actions of the same path win over it.
+    #[track_caller]
+    pub fn any<H, T>(mut self, name: impl AsRef<str>, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        validate_name("action", name.as_ref());
+        self.routers.push(ActionRoute {
+            name: name.as_ref().to_string(),
+            roles: None,
+            csp: None,
+            limit: None,
+            filter: None,
+            candidates: Vec::new(),
+            any: true,
+            router: MethodRouter::new().fallback(handler),
+        });
+        self
+    }
+
+    #[track_caller]
+    pub(crate) fn on_service<T>(self, filter: MethodFilter, name: &str, service: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+        T::Response: IntoResponse + 'static,
+        T::Future: Send + 'static,
+        S: Send + Sync + 'static,
+    {
+        self.on_router(
+            filter,
+            name,
+            MethodRouter::new().on_service(filter, service),
+        )
+    }
+
+    /// Chain a method router of the methods in the filter
+    #[track_caller]
+    pub(crate) fn on_router(
+        mut self,
+        filter: MethodFilter,
+        name: &str,
+        router: MethodRouter<S>,
+    ) -> Self {
+        validate_name("action", name);
+        self.routers.push(ActionRoute {
+            name: name.to_string(),
+            roles: None,
+            csp: None,
+            limit: None,
+            filter: Some(filter),
+            candidates: Vec::new(),
+            any: false,
+            router,
+        });
+        self
+    }
+
+    chained_service_fn!(delete_service, DELETE);
+    chained_service_fn!(get_service, GET);
+    chained_service_fn!(head_service, HEAD);
+    chained_service_fn!(options_service, OPTIONS);
+    chained_service_fn!(patch_service, PATCH);
+    chained_service_fn!(post_service, POST);
+    chained_service_fn!(put_service, PUT);
+    chained_service_fn!(trace_service, TRACE);
+
+    /// Chain an additional service that will accept the requests of any method,
+    /// like a `ServeDir` or a reverse proxy
+    ///
+    /// The service is the method fallback of the path, the method specific
+    /// actions of the same path win over it.
+    #[track_caller]
+    pub fn any_service<T>(mut self, name: impl AsRef<str>, service: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+        T::Response: IntoResponse + 'static,
+        T::Future: Send + 'static,
+        S: Send + Sync + 'static,
+    {
+        validate_name("action", name.as_ref());
+        self.routers.push(ActionRoute {
+            name: name.as_ref().to_string(),
+            roles: None,
+            csp: None,
+            limit: None,
+            filter: None,
+            candidates: Vec::new(),
+            any: true,
+            router: MethodRouter::new().fallback_service(service),
+        });
+        self
+    }
+
+    chained_handler_with_roles_fn!(delete_with_roles, DELETE);
+    chained_handler_with_roles_fn!(get_with_roles, GET);
+    chained_handler_with_roles_fn!(head_with_roles, HEAD);
+    chained_handler_with_roles_fn!(options_with_roles, OPTIONS);
+    chained_handler_with_roles_fn!(patch_with_roles, PATCH);
+    chained_handler_with_roles_fn!(post_with_roles, POST);
+    chained_handler_with_roles_fn!(put_with_roles, PUT);
+    chained_handler_with_roles_fn!(trace_with_roles, TRACE);
 }
 
 top_level_acion_fn!(delete, DELETE);
@@ -138,7 +549,61 @@ top_level_acion_fn!(post, POST);
 top_level_acion_fn!(put, PUT);
 top_level_acion_fn!(trace, TRACE);
 
-fn on<H, T, S>(filter: MethodFilter, name: &str, handler: H) -> Action<S>
+/// Route requests of any method to the given action
+///
+/// The handler is the method fallback of the path, the method specific
+/// actions of the same path win over it.
+#[track_caller]
+pub fn any<H, T, S>(name: impl AsRef<str>, handler: H) -> Action<S>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    Action::new().any(name, handler)
+}
+
+top_level_service_fn!(delete_service, DELETE);
+top_level_service_fn!(get_service, GET);
+top_level_service_fn!(head_service, HEAD);
+top_level_service_fn!(options_service, OPTIONS);
+top_level_service_fn!(patch_service, PATCH);
+top_level_service_fn!(post_service, POST);
+top_level_service_fn!(put_service, PUT);
+top_level_service_fn!(trace_service, TRACE);
+
+/// Route requests of any method to the given service, like a `ServeDir`
+/// or a reverse proxy
+///
+/// The service is the method fallback of the path, the method specific
+/// actions of the same path win over it.
+#[track_caller]
+pub fn any_service<T, S>(name: impl AsRef<str>, service: T) -> Action<S>
+where
+    T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+    T::Response: IntoResponse + 'static,
+    T::Future: Send + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    Action::new().any_service(name, service)
+}
+
+top_level_action_with_roles_fn!(delete_with_roles, DELETE);
+top_level_action_with_roles_fn!(get_with_roles, GET);
+top_level_action_with_roles_fn!(head_with_roles, HEAD);
+top_level_action_with_roles_fn!(options_with_roles, OPTIONS);
+top_level_action_with_roles_fn!(patch_with_roles, PATCH);
+top_level_action_with_roles_fn!(post_with_roles, POST);
+top_level_action_with_roles_fn!(put_with_roles, PUT);
+top_level_action_with_roles_fn!(trace_with_roles, TRACE);
+
+/// Route requests of the methods in the filter to the given action, like
+/// `axum::routing::on`
+///
+/// A combined filter, like `MethodFilter::GET.or(MethodFilter::HEAD)`, is one
+/// guarded entry with one action for all of its methods.
+#[track_caller]
+pub fn on<H, T, S>(filter: MethodFilter, name: impl AsRef<str>, handler: H) -> Action<S>
 where
     H: Handler<T, S>,
     T: 'static,
@@ -146,3 +611,190 @@ where
 {
     Action::new().on(filter, name, handler)
 }
+
+/// A handler taking the [`GuardInfo`] of the allowed request as its first
+/// argument, see `GuardRouter::action_with_decision`
+///
+/// It is implemented for the async functions and closures whose first
+/// argument is `GuardInfo`, followed by up to 8 extractors.
+pub trait DecisionHandler<T, S>: Clone + Send + Sized + 'static {
+    /// Add the handler to the action for the methods in the filter
+    fn on(self, action: Action<S>, filter: MethodFilter, name: &str) -> Action<S>;
+}
+
+impl<F, Fut, Res, S> DecisionHandler<((),), S> for F
+where
+    F: FnOnce(GuardInfo) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Res> + Send,
+    Res: IntoResponse,
+    S: Clone + Send + Sync + 'static,
+{
+    #[track_caller]
+    fn on(self, action: Action<S>, filter: MethodFilter, name: &str) -> Action<S> {
+        action.on(filter, name, move |info: GuardInfo| self(info))
+    }
+}
+
+macro_rules! impl_decision_handler {
+    (
+        [$($ty:ident),*], $last:ident
+    ) => {
+        #[allow(non_snake_case)]
+        impl<F, Fut, Res, S, M, $($ty,)* $last> DecisionHandler<(M, $($ty,)* $last,), S> for F
+        where
+            F: FnOnce(GuardInfo, $($ty,)* $last,) -> Fut + Clone + Send + 'static,
+            Fut: Future<Output = Res> + Send,
+            Res: IntoResponse,
+            S: Clone + Send + Sync + 'static,
+            M: 'static,
+            $( $ty: FromRequestParts<S> + Send + 'static, )*
+            $last: FromRequest<S, M> + Send + 'static,
+        {
+            #[track_caller]
+            fn on(self, action: Action<S>, filter: MethodFilter, name: &str) -> Action<S> {
+                action.on(filter, name, move |info: GuardInfo, $($ty: $ty,)* $last: $last| {
+                    self(info, $($ty,)* $last)
+                })
+            }
+        }
+    };
+}
+
+impl_decision_handler!([], T1);
+impl_decision_handler!([T1], T2);
+impl_decision_handler!([T1, T2], T3);
+impl_decision_handler!([T1, T2, T3], T4);
+impl_decision_handler!([T1, T2, T3, T4], T5);
+impl_decision_handler!([T1, T2, T3, T4, T5], T6);
+impl_decision_handler!([T1, T2, T3, T4, T5, T6], T7);
+impl_decision_handler!([T1, T2, T3, T4, T5, T6, T7], T8);
+
+/// The handlers of the conventional routes of a REST resource, see
+/// `GuardRouter::resource`
+///
+/// Each handler is optional, the routes without a handler are not added.
+/// The handlers are set with methods rather than fields, as each one has its
+/// own type.
+///
+/// ```rust, ignore
+///  let users = CrudHandlers::new()
+///     .index(list_users)
+///     .show(get_user)
+///     .create(create_user)
+///     .update(update_user)
+///     .delete(delete_user);
+/// ```
+#[must_use]
+pub struct CrudHandlers<S = ()> {
+    /// The routes with the action suffix and whether they are of an item
+    pub(crate) routes: Vec<(&'static str, bool, MethodFilter, MethodRouter<S>)>,
+}
+
+impl<S> Default for CrudHandlers<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> CrudHandlers<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    fn handler<H, T>(
+        mut self,
+        action: &'static str,
+        item: bool,
+        filter: MethodFilter,
+        handler: H,
+    ) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        let router = MethodRouter::new().on(filter, handler);
+        self.routes.push((action, item, filter, router));
+        self
+    }
+
+    /// List the resources with `GET /users`, the action is `users:list`
+    pub fn index<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.handler("list", false, MethodFilter::GET, handler)
+    }
+
+    /// Get a resource with `GET /users/:id`, the action is `users:get`
+    pub fn show<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.handler("get", true, MethodFilter::GET, handler)
+    }
+
+    /// Create a resource with `POST /users`, the action is `users:create`
+    pub fn create<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.handler("create", false, MethodFilter::POST, handler)
+    }
+
+    /// Update a resource with `PUT /users/:id`, the action is `users:update`
+    pub fn update<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.handler("update", true, MethodFilter::PUT, handler)
+    }
+
+    /// Delete a resource with `DELETE /users/:id`, the action is `users:delete`
+    pub fn delete<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.handler("delete", true, MethodFilter::DELETE, handler)
+    }
+}
+
+/// The methods of a method filter
+pub(crate) fn filter_methods(filter: MethodFilter) -> Vec<Method> {
+    [
+        (MethodFilter::DELETE, Method::DELETE),
+        (MethodFilter::GET, Method::GET),
+        (MethodFilter::HEAD, Method::HEAD),
+        (MethodFilter::OPTIONS, Method::OPTIONS),
+        (MethodFilter::PATCH, Method::PATCH),
+        (MethodFilter::POST, Method::POST),
+        (MethodFilter::PUT, Method::PUT),
+        (MethodFilter::TRACE, Method::TRACE),
+    ]
+    .into_iter()
+    .filter(|(method_filter, _)| filter.or(*method_filter) == filter)
+    .map(|(_, method)| method)
+    .collect()
+}
+
+/// Panic when a resource or action name is empty or contains whitespace,
+/// the guard could never match it
+#[track_caller]
+pub(crate) fn validate_name(kind: &str, name: &str) {
+    if name.is_empty() {
+        panic!("the {kind} name is empty");
+    }
+    if name.chars().any(char::is_whitespace) {
+        panic!("the {kind} name {name:?} contains whitespace");
+    }
+}
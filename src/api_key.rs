@@ -0,0 +1,197 @@
+//! A guard to validate api keys against a key store
+//!
+//! ```rust,ignore
+//!  use std::sync::Arc;
+//!  use axum_guard_router::api_key::{ApiKey, ApiKeyGuard, KeyStore};
+//!
+//!  struct MyStore;
+//!
+//!  impl KeyStore for MyStore {
+//!      async fn lookup(&self, key: &str) -> Option<ApiKey> {
+//!          // load the key from a database
+//!          None
+//!      }
+//!  }
+//!
+//!  let guard = ApiKeyGuard::new(Arc::new(MyStore));
+//!  let router = GuardRouter::new("my:router:resource", Arc::new(guard))
+//!     .action("my:get", "/user", get(handler));
+//! ```
+use crate::{GuardContext, OnGuard};
+use axum::{
+    http::{HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::{future::Future, sync::Arc};
+
+/// The default header to read the api key from
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// A resolved api key with its scopes
+///
+/// It is inserted into the request extensions when the request is allowed,
+/// so handlers can use `Extension<ApiKey>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: String,
+    pub scopes: Vec<String>,
+}
+
+impl ApiKey {
+    pub fn new(id: &str, scopes: &[&str]) -> Self {
+        Self {
+            id: id.to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        }
+    }
+
+    /// Check if the scopes cover the action
+    /// a scope matches the action name, `*` matches every action
+    /// and `my:*` matches every action starting with `my:`
+    pub fn covers(&self, action: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| match scope.strip_suffix('*') {
+                Some(prefix) => action.starts_with(prefix),
+                None => scope == action,
+            })
+    }
+}
+
+/// A store to look up api keys
+pub trait KeyStore {
+    /// Look up the key, return `None` if it is unknown
+    fn lookup(&self, key: &str) -> impl Future<Output = Option<ApiKey>> + Send;
+}
+
+/// A guard to validate the api key in the request header
+///
+/// It denies with 401 when the key is missing or unknown,
+/// and with 403 when the key scopes do not cover the action.
+pub struct ApiKeyGuard<K> {
+    pub store: Arc<K>,
+    pub header: HeaderName,
+}
+
+impl<K> ApiKeyGuard<K> {
+    /// Create a guard reading the key from the `x-api-key` header
+    pub fn new(store: Arc<K>) -> Self {
+        Self {
+            store,
+            header: HeaderName::from_static(API_KEY_HEADER),
+        }
+    }
+
+    /// Set the header to read the key from
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+}
+
+impl<K> Clone for ApiKeyGuard<K> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            header: self.header.clone(),
+        }
+    }
+}
+
+impl<K> OnGuard for ApiKeyGuard<K>
+where
+    K: KeyStore + Send + Sync,
+{
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        let key = ctx
+            .headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "missing api key").into_response())?;
+
+        let Some(api_key) = self.store.lookup(key).await else {
+            return Err((StatusCode::UNAUTHORIZED, "unknown api key").into_response());
+        };
+        if !api_key.covers(ctx.action()) {
+            log::debug!(
+                "ApiKeyGuard: key={} does not cover action={}",
+                api_key.id,
+                ctx.action()
+            );
+            return Err((StatusCode::FORBIDDEN, "api key scope not allowed").into_response());
+        }
+
+        ctx.insert(api_key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiKey, ApiKeyGuard, KeyStore};
+    use crate::test_helper::TestClient;
+    use crate::GuardRouter;
+    use axum::{routing::get, Extension};
+    use reqwest::StatusCode;
+    use std::{collections::HashMap, sync::Arc};
+
+    struct TestStore(HashMap<String, ApiKey>);
+
+    impl KeyStore for TestStore {
+        async fn lookup(&self, key: &str) -> Option<ApiKey> {
+            self.0.get(key).cloned()
+        }
+    }
+
+    fn app() -> axum::Router {
+        let mut keys = HashMap::new();
+        keys.insert("key1".to_string(), ApiKey::new("reader", &["my:get"]));
+        keys.insert("key2".to_string(), ApiKey::new("admin", &["my:*"]));
+        let guard = ApiKeyGuard::new(Arc::new(TestStore(keys)));
+        GuardRouter::new("my:test", Arc::new(guard))
+            .action("my:get", "/test", get(handler))
+            .action("my:update", "/update", get(handler))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_api_key_unknown_401() {
+        let client = TestClient::new(app());
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let status = client
+            .get("/test")
+            .header("x-api-key", "key3")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_scope_403() {
+        let client = TestClient::new(app());
+        let status = client
+            .get("/update")
+            .header("x-api-key", "key1")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_pass() {
+        let client = TestClient::new(app());
+        let response = client.get("/test").header("x-api-key", "key1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "reader");
+
+        let response = client.get("/update").header("x-api-key", "key2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "admin");
+    }
+
+    async fn handler(Extension(key): Extension<ApiKey>) -> String {
+        key.id
+    }
+}
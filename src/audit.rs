@@ -0,0 +1,292 @@
+//! A guard wrapper to record the guard decisions into an append-only file
+//!
+//! ```rust,ignore
+//!  use std::sync::Arc;
+//!  use axum_guard_router::audit::FileAuditGuard;
+//!
+//!  let guard = FileAuditGuard::open("/var/log/guard-audit.log", MyGuard)
+//!     .await
+//!     .unwrap()
+//!     .principal(|ctx| {
+//!         ctx.headers()
+//!             .get("x-user")
+//!             .and_then(|value| value.to_str().ok())
+//!             .map(|user| user.to_string())
+//!     });
+//!  let router = GuardRouter::new("my:router:resource", Arc::new(guard))
+//!     .action("my:get", "/user", get(handler));
+//! ```
+use crate::{GuardContext, OnGuard};
+use std::{
+    io,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+    sync::{mpsc, oneshot},
+};
+
+type PrincipalFn = dyn Fn(&GuardContext<'_>) -> Option<String> + Send + Sync;
+
+enum Message {
+    Record(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// A guard to record every decision of the inner guard without changing it
+///
+/// Each request is appended to the file once, with the final decision of the
+/// guard layer, as one tab separated line:
+/// `timestamp(ms) decision principal resource action`, where decision is
+/// `allow`, `deny`, `challenge`, `pending`, `error` or `skip`. The denies of
+/// the authentication and the roles checks are recorded too, and the action
+/// is the chosen one of the candidates. The lines are written by a background
+/// task with a buffered writer, which is flushed whenever no record is waiting.
+pub struct FileAuditGuard<G> {
+    inner: G,
+    sender: mpsc::UnboundedSender<Message>,
+    principal: Option<Arc<PrincipalFn>>,
+}
+
+impl<G> FileAuditGuard<G> {
+    /// Open the audit file in append mode and spawn the writer task,
+    /// it must be called inside a tokio runtime
+    pub async fn open(path: impl AsRef<Path>, inner: G) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write(file, receiver));
+
+        Ok(Self {
+            inner,
+            sender,
+            principal: None,
+        })
+    }
+
+    /// Set the function to resolve the principal of the request
+    /// the principal is recorded as `-` when it is not set or returns `None`
+    pub fn principal<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&GuardContext<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.principal = Some(Arc::new(f));
+        self
+    }
+
+    /// Wait until all the recorded decisions are written to the file
+    pub async fn flush(&self) {
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(Message::Flush(ack)).is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    /// Record the final decision of the request layer with the principal
+    fn record(&self, ctx: &GuardContext<'_>) {
+        let principal = self
+            .principal
+            .as_ref()
+            .and_then(|f| f(ctx))
+            .unwrap_or_else(|| "-".to_string());
+        let sender = self.sender.clone();
+        ctx.on_decided(move |resource, action, decision| {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis())
+                .unwrap_or_default();
+            let line = format!("{timestamp}\t{decision}\t{principal}\t{resource}\t{action}\n");
+            if sender.send(Message::Record(line)).is_err() {
+                log::error!("FileAuditGuard: the writer task is stopped");
+            }
+        });
+    }
+}
+
+impl<G> Clone for FileAuditGuard<G>
+where
+    G: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            sender: self.sender.clone(),
+            principal: self.principal.clone(),
+        }
+    }
+}
+
+impl<G> OnGuard for FileAuditGuard<G>
+where
+    G: OnGuard,
+{
+    async fn on_authenticate(&self, ctx: &GuardContext<'_>) -> Result<(), String> {
+        self.record(ctx);
+        self.inner.on_authenticate(ctx).await
+    }
+
+    forward_hooks!(
+        OnGuard,
+        [inner];
+        on_roles,
+        on_roles_for,
+        on_roles_for_request,
+        on_guard_decision,
+        observers,
+    );
+}
+
+async fn write(file: File, mut receiver: mpsc::UnboundedReceiver<Message>) {
+    let mut writer = BufWriter::new(file);
+    while let Some(message) = receiver.recv().await {
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                Message::Record(line) => {
+                    if let Err(err) = writer.write_all(line.as_bytes()).await {
+                        log::error!("FileAuditGuard: failed to write the record: {err}");
+                    }
+                }
+                Message::Flush(ack) => {
+                    if let Err(err) = writer.flush().await {
+                        log::error!("FileAuditGuard: failed to flush the records: {err}");
+                    }
+                    let _ = ack.send(());
+                }
+            }
+            next = receiver.try_recv().ok();
+        }
+        if let Err(err) = writer.flush().await {
+            log::error!("FileAuditGuard: failed to flush the records: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileAuditGuard;
+    use crate::test_helper::{TestClient, TestGuard};
+    use crate::{GuardContext, GuardRouter, OnGuard};
+    use axum::{
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use reqwest::StatusCode;
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::SystemTime,
+    };
+
+    fn audit_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "axum-guard-router-audit-{}.log",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ))
+    }
+
+    fn read_records(path: &Path) -> Vec<Vec<String>> {
+        let content = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        content
+            .lines()
+            .map(|line| line.split('\t').skip(1).map(str::to_string).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_file_audit_records_decisions() {
+        let path = audit_path();
+        let guard = FileAuditGuard::open(&path, TestGuard::new_with(true, true))
+            .await
+            .unwrap()
+            .principal(|ctx| {
+                ctx.headers()
+                    .get("x-user")
+                    .and_then(|value| value.to_str().ok())
+                    .map(|user| user.to_string())
+            });
+        let deny = FileAuditGuard::open(&path, TestGuard::new()).await.unwrap();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(handler))
+            .build()
+            .merge(
+                GuardRouter::new("my:test", Arc::new(deny.clone()))
+                    .action("action2", "/deny", get(handler))
+                    .build(),
+            );
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").header("x-user", "alice").await.status();
+        assert_eq!(status, StatusCode::OK);
+        guard.flush().await;
+
+        let status = client.get("/deny").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        deny.flush().await;
+
+        assert_eq!(
+            read_records(&path),
+            vec![
+                vec!["allow", "alice", "my:test", "action1"],
+                vec!["deny", "-", "my:test", "action2"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_audit_records_authentication_and_roles() {
+        #[derive(Clone)]
+        struct TokenGuard;
+
+        impl OnGuard for TokenGuard {
+            async fn on_authenticate(&self, ctx: &GuardContext<'_>) -> Result<(), String> {
+                match ctx.headers().contains_key("authorization") {
+                    true => Ok(()),
+                    false => Err("Bearer".to_string()),
+                }
+            }
+
+            async fn on_roles(&self, _roles: &[String]) -> Result<(), Response> {
+                Err(StatusCode::FORBIDDEN.into_response())
+            }
+        }
+
+        let path = audit_path();
+        let guard = FileAuditGuard::open(&path, TokenGuard).await.unwrap();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(handler))
+            .action_with_roles("action2", "/admin", &["admin".to_string()], get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let status = client
+            .get("/admin")
+            .header("authorization", "Bearer token")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        guard.flush().await;
+
+        assert_eq!(
+            read_records(&path),
+            vec![
+                vec!["challenge", "-", "my:test", "action1"],
+                vec!["deny", "-", "my:test", "action2"],
+            ]
+        );
+    }
+
+    async fn handler() {}
+}
@@ -0,0 +1,198 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    response::Response,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+type KeyFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+/// The maximum number of cached responses, the first to expire is evicted
+const MAX_ENTRIES: usize = 1024;
+/// The maximum body size of a cached response, the larger ones are not cached
+const MAX_BODY: u64 = 1024 * 1024;
+
+struct Entry {
+    expires: Instant,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// The cached handler responses of a guard router
+///
+/// Only the `GET` requests with a key are cached, and only the successful
+/// responses are stored. The entries are keyed by the action and the key
+/// function result, and expire after the ttl. The responses setting a cookie,
+/// marked `Cache-Control: private` or `no-store`, or with a body over 1 MiB
+/// or of an unknown size are not stored, and at most 1024 entries are kept.
+pub struct ResponseCache {
+    ttl: Duration,
+    key: Box<KeyFn>,
+    entries: Mutex<HashMap<(String, String), Entry>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new<F>(ttl: Duration, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            ttl,
+            key: Box::new(key),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cache key of the request, `None` when it is not cacheable
+    pub(crate) fn key(&self, action: &str, parts: &Parts) -> Option<(String, String)> {
+        if parts.method != Method::GET {
+            return None;
+        }
+        (self.key)(parts).map(|key| (action.to_string(), key))
+    }
+
+    pub(crate) fn get(&self, key: &(String, String)) -> Option<Response> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(key)
+            .filter(|entry| entry.expires > Instant::now())?;
+
+        let mut response = Response::new(Body::from(entry.body.clone()));
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers.clone();
+        Some(response)
+    }
+
+    /// Store the handler response when it is successful and shareable,
+    /// and return it with the buffered body
+    pub(crate) async fn put(&self, key: (String, String), response: Response) -> Response {
+        if !response.status().is_success() || !is_shareable(response.headers()) {
+            return response;
+        }
+        match response.body().size_hint().upper() {
+            Some(size) if size <= MAX_BODY => {}
+            _ => return response,
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = match axum::body::to_bytes(body, MAX_BODY as usize).await {
+            Ok(body) => body,
+            Err(err) => {
+                log::error!("ResponseCache: failed to read the response body: {err}");
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+        };
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires > now);
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&key) {
+            let first = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone());
+            if let Some(first) = first {
+                entries.remove(&first);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                expires: now + self.ttl,
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            },
+        );
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+/// Whether the response can be served to other callers with the same key
+fn is_shareable(headers: &HeaderMap) -> bool {
+    if headers.contains_key(header::SET_COOKIE) {
+        return false;
+    }
+    !headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim())
+        .any(|directive| {
+            directive.eq_ignore_ascii_case("private") || directive.eq_ignore_ascii_case("no-store")
+        })
+}
+
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResponseCache, MAX_BODY, MAX_ENTRIES};
+    use axum::{
+        body::Body,
+        http::{header, HeaderValue},
+        response::{IntoResponse, Response},
+    };
+    use std::time::Duration;
+
+    fn key(key: &str) -> (String, String) {
+        ("action1".to_string(), key.to_string())
+    }
+
+    async fn put(cache: &ResponseCache, name: &str, response: Response) -> bool {
+        cache.put(key(name), response).await;
+        cache.get(&key(name)).is_some()
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_private_responses() {
+        let cache = ResponseCache::new(Duration::from_secs(60), |_| None);
+        assert!(put(&cache, "public", "ok".into_response()).await);
+
+        let mut response = "ok".into_response();
+        let cookie = HeaderValue::from_static("session=1");
+        response.headers_mut().insert(header::SET_COOKIE, cookie);
+        assert!(!put(&cache, "cookie", response).await);
+
+        for directive in ["private, max-age=60", "No-Store"] {
+            let mut response = "ok".into_response();
+            let value = HeaderValue::from_static(directive);
+            response.headers_mut().insert(header::CACHE_CONTROL, value);
+            assert!(!put(&cache, directive, response).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_bounds() {
+        let cache = ResponseCache::new(Duration::from_secs(60), |_| None);
+        let body = vec![0u8; MAX_BODY as usize + 1];
+        assert!(!put(&cache, "large", body.into_response()).await);
+
+        // a streamed body has no known size
+        let stream = Body::from_stream(futures::stream::iter([Ok::<_, std::io::Error>("ok")]));
+        assert!(!put(&cache, "stream", Response::new(stream)).await);
+
+        for index in 0..=MAX_ENTRIES {
+            cache
+                .put(key(&index.to_string()), "ok".into_response())
+                .await;
+        }
+        assert_eq!(cache.entries.lock().unwrap().len(), MAX_ENTRIES);
+        assert!(cache.get(&key(&MAX_ENTRIES.to_string())).is_some());
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::HashMap, sync::RwLock};
+
+/// Enforcement mode of a guarded resource
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Enforcement {
+    /// Run the guard and return its denial
+    #[default]
+    Enforce,
+    /// Run the guard but only log its denial, the handler is still called
+    Shadow,
+    /// Do not run the guard
+    Off,
+}
+
+/// A runtime registry of the enforcement mode per resource
+///
+/// The guard service reads the registry on every request, so a mode change
+/// takes effect without rebuilding the router.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use std::sync::Arc;
+///  use axum_guard_router::{Enforcement, GuardConfig, GuardRouter};
+///
+///  let config = Arc::new(GuardConfig::new());
+///  config.set("my:router:admin", Enforcement::Shadow);
+///
+///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+///     .config(config.clone())
+///     .action("my:get", "/admin", get(handler));
+/// ```
+#[derive(Debug, Default)]
+pub struct GuardConfig {
+    default: Enforcement,
+    modes: RwLock<HashMap<String, Enforcement>>,
+}
+
+impl GuardConfig {
+    /// Create a registry where every resource is enforced by default
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry with the mode for resources not set explicitly
+    pub fn with_default(default: Enforcement) -> Self {
+        Self {
+            default,
+            modes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Set the enforcement mode of a resource
+    pub fn set(&self, resource: &str, mode: Enforcement) {
+        self.modes
+            .write()
+            .unwrap()
+            .insert(resource.to_string(), mode);
+    }
+
+    /// Remove the mode of a resource, it falls back to the default mode
+    pub fn remove(&self, resource: &str) {
+        self.modes.write().unwrap().remove(resource);
+    }
+
+    /// Get the current enforcement mode of a resource
+    pub fn mode(&self, resource: &str) -> Enforcement {
+        self.modes
+            .read()
+            .unwrap()
+            .get(resource)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
@@ -0,0 +1,396 @@
+use crate::{Policy, RequestId};
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts, Extensions, HeaderMap, Method, Request, StatusCode, Uri},
+    response::Response,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{Mutex, OnceLock},
+};
+
+type DecidedFn = Box<dyn FnOnce(&str, &str, &str) + Send>;
+
+/// The observers of the final decision of a layer
+#[derive(Default)]
+pub(crate) struct Decided(Vec<DecidedFn>);
+
+impl Decided {
+    /// Notify the observers of the decision with its resource and action
+    pub(crate) fn notify(self, resource: &str, action: &str, decision: &str) {
+        for f in self.0 {
+            f(resource, action, decision);
+        }
+    }
+}
+
+impl fmt::Debug for Decided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decided").finish_non_exhaustive()
+    }
+}
+
+/// The resource, action and roles of the guard which passed the request
+///
+/// It is added to the request extensions of an allowed request before
+/// calling the handler, so handlers can use `Extension<GuardInfo>` or take it
+/// as an argument. With nested guard routers, the innermost guard wins. It is
+/// not added when the guard is bypassed, or for a shadow mode deny.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuardInfo {
+    pub resource: String,
+    pub action: String,
+    pub roles: Option<Vec<String>>,
+}
+
+impl From<&Policy> for GuardInfo {
+    fn from(policy: &Policy) -> Self {
+        Self {
+            resource: policy.resource.clone(),
+            action: policy.action.clone(),
+            roles: policy.roles.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for GuardInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<GuardInfo>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the handler is not guarded by a guard router",
+        ))
+    }
+}
+
+/// The router state in the request extensions, see [`GuardContext::router_state`]
+#[derive(Clone)]
+pub(crate) struct GuardState<S>(pub(crate) S);
+
+/// The checks passed by the guard layers of a request, to run them once
+///
+/// It is kept in the request extensions by the layers with `cached`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GuardCache {
+    roles: BTreeSet<Vec<String>>,
+    allowed: BTreeSet<(String, String)>,
+}
+
+impl GuardCache {
+    pub(crate) fn has_roles(&self, roles: &[String]) -> bool {
+        self.roles.contains(roles)
+    }
+
+    pub(crate) fn pass_roles(&mut self, roles: &[String]) {
+        self.roles.insert(roles.to_vec());
+    }
+
+    pub(crate) fn is_allowed(&self, resource: &str, action: &str) -> bool {
+        self.allowed
+            .contains(&(resource.to_string(), action.to_string()))
+    }
+
+    pub(crate) fn allow(&mut self, resource: &str, action: &str) {
+        self.allowed
+            .insert((resource.to_string(), action.to_string()));
+    }
+}
+
+/// The limits of the authorized scope, like the maximum page size of lists
+///
+/// A guard sets them with [`GuardContext::insert`] on allow, and the handlers
+/// read them with `Extension<QueryLimits>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryLimits {
+    pub max_page_size: usize,
+}
+
+impl QueryLimits {
+    pub fn new(max_page_size: usize) -> Self {
+        Self { max_page_size }
+    }
+
+    /// Clamp the requested page size to the maximum
+    pub fn page_size(&self, requested: Option<usize>) -> usize {
+        requested.map_or(self.max_page_size, |size| size.min(self.max_page_size))
+    }
+}
+
+/// The request context passed to `OnGuard::on_request`
+///
+/// The context borrows the request head (method, uri, headers) and the
+/// guard policy. The borrows only live while the guard hooks run: the
+/// request is split before the checks and put back together once they
+/// finish, so the context cannot be kept. The body is only available with
+/// `GuardRouter::buffer_body`, which reads it up to the limit before the
+/// checks, answers a larger body with `413 Payload Too Large`, a body
+/// failing to be read with `400 Bad Request`, and passes the buffered body
+/// on to the handler.
+///
+/// Besides reading the request, a guard can insert values with
+/// [`GuardContext::insert`], they are added to the request extensions when
+/// the request is allowed, so handlers can use them with `Extension<T>`.
+#[derive(Debug)]
+pub struct GuardContext<'a> {
+    policy: &'a Policy,
+    /// The candidate action in place of the policy action
+    action: Option<&'a str>,
+    parts: &'a Parts,
+    params: BTreeMap<String, String>,
+    query: OnceLock<BTreeMap<String, String>>,
+    body: Option<Bytes>,
+    extensions: Mutex<Extensions>,
+    /// The layer decides the resource and action after the roles
+    decides: bool,
+    /// The deny of a combined guard found while authenticating
+    denied: Mutex<Option<Response>>,
+    /// Called with the final decision of the layer
+    decided: Mutex<Decided>,
+}
+
+impl<'a> GuardContext<'a> {
+    pub(crate) fn new(
+        policy: &'a Policy,
+        parts: &'a Parts,
+        params: BTreeMap<String, String>,
+        body: Option<Bytes>,
+    ) -> Self {
+        Self {
+            policy,
+            action: None,
+            parts,
+            params,
+            query: OnceLock::new(),
+            body,
+            extensions: Mutex::new(Extensions::new()),
+            decides: true,
+            denied: Mutex::new(None),
+            decided: Mutex::new(Decided::default()),
+        }
+    }
+
+    /// Mark a context of a layer which does not decide the resource and
+    /// action, like a roles only layer
+    pub(crate) fn without_decision(mut self) -> Self {
+        self.decides = false;
+        self
+    }
+
+    /// A context of the same request to decide a candidate action
+    ///
+    /// The values inserted into it are not kept, use [`GuardContext::extend`]
+    /// to keep the ones of the chosen candidate.
+    pub fn with_action<'b>(&'b self, action: &'b str) -> GuardContext<'b> {
+        GuardContext {
+            policy: self.policy,
+            action: Some(action),
+            parts: self.parts,
+            params: self.params.clone(),
+            query: self.query.clone(),
+            body: self.body.clone(),
+            extensions: Mutex::new(Extensions::new()),
+            decides: self.decides,
+            denied: Mutex::new(None),
+            decided: Mutex::new(Decided::default()),
+        }
+    }
+
+    /// The guarded resource
+    pub fn resource(&self) -> &str {
+        &self.policy.resource
+    }
+
+    /// The guarded action
+    pub fn action(&self) -> &str {
+        self.action.unwrap_or(&self.policy.action)
+    }
+
+    /// The request method
+    pub fn method(&self) -> &Method {
+        &self.parts.method
+    }
+
+    /// The request uri, inside a nested router the nest prefix is stripped
+    pub fn uri(&self) -> &Uri {
+        &self.parts.uri
+    }
+
+    /// The matched path parameters, empty when the route has none
+    ///
+    /// When a parameter name repeats in the route, the last segment wins.
+    pub fn params(&self) -> &BTreeMap<String, String> {
+        &self.params
+    }
+
+    /// The query string parameters, parsed on the first call
+    ///
+    /// When a parameter name repeats, the last value wins. An invalid query
+    /// string has no parameters.
+    pub fn query(&self) -> &BTreeMap<String, String> {
+        self.query.get_or_init(|| {
+            Query::<BTreeMap<String, String>>::try_from_uri(&self.parts.uri)
+                .map(|Query(query)| query)
+                .unwrap_or_default()
+        })
+    }
+
+    /// The request headers
+    pub fn headers(&self) -> &HeaderMap {
+        &self.parts.headers
+    }
+
+    /// The buffered request body, when the router is built with
+    /// `buffer_body`, `None` otherwise
+    pub fn body(&self) -> Option<&Bytes> {
+        self.body.as_ref()
+    }
+
+    /// The request extensions, like the caller added by an outer middleware
+    ///
+    /// The values inserted with [`GuardContext::insert`] are not in them.
+    pub fn extensions(&self) -> &Extensions {
+        &self.parts.extensions
+    }
+
+    /// The value of a request cookie, like a session id
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find_map(|(key, value)| (key == name).then_some(value))
+    }
+
+    /// The request id, when the router is built with `with_request_id`
+    pub fn request_id(&self) -> Option<&str> {
+        self.parts
+            .extensions
+            .get::<RequestId>()
+            .map(|id| id.as_str())
+    }
+
+    /// The state given to the guard router with `GuardRouter::with_state`
+    ///
+    /// It is only set by `GuardRouter::with_state`, a layer can not read the state
+    /// of an axum router. So it is `None` for the guard routers built into a
+    /// router which gets its state later, like with the `with_state` of an
+    /// outer router, and for another state type. The state is
+    /// `S: Clone + Send + Sync` like the handler `State<S>`.
+    pub fn router_state<S>(&self) -> Option<&S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.parts
+            .extensions
+            .get::<GuardState<S>>()
+            .map(|state| &state.0)
+    }
+
+    /// Insert a value into the request extensions once the request is allowed
+    pub fn insert<T>(&self, value: T)
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.extensions.lock().unwrap().insert(value);
+    }
+
+    /// Run an extractor on a copy of the request head, like a `Claims`
+    /// extractor parsing the token
+    ///
+    /// The extractor runs with the `()` state, see
+    /// [`GuardContext::extract_with_state`] for the extractors of a state.
+    /// The extensions it inserts are not kept.
+    pub async fn extract<E>(&self) -> Result<E, E::Rejection>
+    where
+        E: FromRequestParts<()>,
+    {
+        self.extract_with_state(&()).await
+    }
+
+    /// Run an extractor of the state on a copy of the request head, like
+    /// with the state of [`GuardContext::router_state`]
+    pub async fn extract_with_state<E, S>(&self, state: &S) -> Result<E, E::Rejection>
+    where
+        E: FromRequestParts<S>,
+    {
+        let (mut parts, _) = Request::new(()).into_parts();
+        parts.method = self.parts.method.clone();
+        parts.uri = self.parts.uri.clone();
+        parts.version = self.parts.version;
+        parts.headers = self.parts.headers.clone();
+        parts.extensions = self.parts.extensions.clone();
+        E::from_request_parts(&mut parts, state).await
+    }
+
+    /// A value inserted with [`GuardContext::insert`], like the tenant resolved
+    /// by a previous stage of a `GuardStack`
+    pub fn get<T>(&self) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.extensions.lock().unwrap().get::<T>().cloned()
+    }
+
+    /// Move the inserted values of another context into this one
+    pub fn extend(&self, other: GuardContext<'_>) {
+        let decided = std::mem::take(&mut other.decided.lock().unwrap().0);
+        self.decided.lock().unwrap().0.extend(decided);
+        self.extensions
+            .lock()
+            .unwrap()
+            .extend(other.into_extensions());
+    }
+
+    /// The policy of the guarded route, with its roles
+    pub(crate) fn policy(&self) -> &Policy {
+        self.policy
+    }
+
+    /// Whether `OnGuard::on_guard_decision` runs after the roles
+    pub(crate) fn decides(&self) -> bool {
+        self.decides
+    }
+
+    /// Deny the request once the authentication passes, for the combined
+    /// guards checking the roles of each guard with its authentication
+    pub(crate) fn deny(&self, response: Response) {
+        *self.denied.lock().unwrap() = Some(response);
+    }
+
+    pub(crate) fn take_denied(&self) -> Option<Response> {
+        self.denied.lock().unwrap().take()
+    }
+
+    /// Observe the final decision of the layer, like `allow` or `deny`,
+    /// with its resource and chosen action
+    #[cfg_attr(not(feature = "audit-file"), allow(dead_code))]
+    pub(crate) fn on_decided<F>(&self, f: F)
+    where
+        F: FnOnce(&str, &str, &str) + Send + 'static,
+    {
+        self.decided.lock().unwrap().0.push(Box::new(f));
+    }
+
+    pub(crate) fn into_extensions(self) -> Extensions {
+        self.into_parts().0
+    }
+
+    /// The inserted values and the decision observers of the context
+    pub(crate) fn into_parts(self) -> (Extensions, Decided) {
+        (
+            self.extensions.into_inner().unwrap(),
+            self.decided.into_inner().unwrap(),
+        )
+    }
+}
@@ -0,0 +1,167 @@
+//! A guard to check the plan entitlements of the principal
+//!
+//! ```rust,ignore
+//!  use std::sync::Arc;
+//!  use axum_guard_router::entitlement::{EntitlementGuard, EntitlementService};
+//!
+//!  struct Billing;
+//!
+//!  impl EntitlementService for Billing {
+//!      async fn is_entitled(&self, principal: &str, feature: &str) -> bool {
+//!          // ask the billing service
+//!          false
+//!      }
+//!  }
+//!
+//!  let guard = EntitlementGuard::new(Arc::new(Billing), |ctx| {
+//!         ctx.headers()
+//!             .get("x-tenant")
+//!             .and_then(|value| value.to_str().ok())
+//!             .map(|tenant| tenant.to_string())
+//!     })
+//!     .feature("my:export", "reports.export");
+//!  let router = GuardRouter::new("my:router:report", Arc::new(guard))
+//!     .action("my:export", "/export", post(handler));
+//! ```
+use crate::{GuardContext, OnGuard};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+type PrincipalFn = dyn Fn(&GuardContext<'_>) -> Option<String> + Send + Sync;
+
+/// A service to check whether the plan of a principal includes a feature
+pub trait EntitlementService {
+    fn is_entitled(&self, principal: &str, feature: &str) -> impl Future<Output = bool> + Send;
+}
+
+/// A guard to check the feature mapped from the action is in the principal plan
+///
+/// The actions without a feature pass. It denies with 401 when the principal
+/// can not be resolved, and with 402 when the plan does not include the feature.
+pub struct EntitlementGuard<E> {
+    pub service: Arc<E>,
+    features: HashMap<String, String>,
+    principal: Arc<PrincipalFn>,
+}
+
+impl<E> EntitlementGuard<E> {
+    /// Create a guard with the entitlement service and the function to resolve
+    /// the principal of the request
+    pub fn new<F>(service: Arc<E>, principal: F) -> Self
+    where
+        F: Fn(&GuardContext<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            service,
+            features: HashMap::new(),
+            principal: Arc::new(principal),
+        }
+    }
+
+    /// Map the action to the feature it requires
+    pub fn feature(mut self, action: &str, feature: &str) -> Self {
+        self.features
+            .insert(action.to_string(), feature.to_string());
+        self
+    }
+}
+
+impl<E> Clone for EntitlementGuard<E> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            features: self.features.clone(),
+            principal: self.principal.clone(),
+        }
+    }
+}
+
+impl<E> OnGuard for EntitlementGuard<E>
+where
+    E: EntitlementService + Send + Sync,
+{
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        let Some(feature) = self.features.get(ctx.action()) else {
+            return Ok(());
+        };
+        let principal = (self.principal)(ctx)
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "missing principal").into_response())?;
+
+        if !self.service.is_entitled(&principal, feature).await {
+            log::debug!("EntitlementGuard: principal={principal} is not entitled to {feature}");
+            return Err((StatusCode::PAYMENT_REQUIRED, "feature not in plan").into_response());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntitlementGuard, EntitlementService};
+    use crate::test_helper::TestClient;
+    use crate::GuardRouter;
+    use axum::routing::get;
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    struct TestBilling;
+
+    impl EntitlementService for TestBilling {
+        async fn is_entitled(&self, principal: &str, feature: &str) -> bool {
+            principal == "pro" && feature == "reports.export"
+        }
+    }
+
+    fn client() -> TestClient {
+        let guard = EntitlementGuard::new(Arc::new(TestBilling), |ctx| {
+            ctx.headers()
+                .get("x-tenant")
+                .and_then(|value| value.to_str().ok())
+                .map(|tenant| tenant.to_string())
+        })
+        .feature("my:export", "reports.export");
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("my:export", "/export", get(handler))
+            .action("my:get", "/test", get(handler))
+            .build();
+        TestClient::new(router)
+    }
+
+    #[tokio::test]
+    async fn test_entitlement_pass() {
+        let client = client();
+        let status = client
+            .get("/export")
+            .header("x-tenant", "pro")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+
+        let status = client
+            .get("/test")
+            .header("x-tenant", "free")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_entitlement_not_in_plan_402() {
+        let client = client();
+        let status = client
+            .get("/export")
+            .header("x-tenant", "free")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+
+        let status = client.get("/export").await.status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn handler() {}
+}
@@ -0,0 +1,107 @@
+use crate::Decision;
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// A guard error to build the deny response
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::{GuardError, OnGuard};
+///
+///  impl OnGuard for MyGuard {
+///      async fn on_guard(&self, resource: &str, action: &str) -> Result<(), Response> {
+///          Err(GuardError::sso_redirect("https://sso.example.com/login").into_response())
+///      }
+///  }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuardError {
+    pub status: StatusCode,
+    pub location: Option<String>,
+    pub message: String,
+}
+
+impl GuardError {
+    /// Create an error with the status and message
+    pub fn new(status: StatusCode, message: &str) -> Self {
+        Self {
+            status,
+            location: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Redirect to a single sign-on flow with `307 Temporary Redirect`
+    ///
+    /// Unlike `302 Found`, the client repeats the request with the same method
+    /// and body at the location, so a denied `POST` is posted again after the
+    /// sign-on.
+    pub fn sso_redirect(location: &str) -> Self {
+        Self {
+            status: StatusCode::TEMPORARY_REDIRECT,
+            location: Some(location.to_string()),
+            message: String::new(),
+        }
+    }
+}
+
+impl IntoResponse for GuardError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.message).into_response();
+        if let Some(location) = self.location {
+            match HeaderValue::from_str(&location) {
+                Ok(value) => {
+                    response.headers_mut().insert(header::LOCATION, value);
+                }
+                Err(_) => log::error!("GuardError: invalid location={location}"),
+            }
+        }
+        response
+    }
+}
+
+impl From<GuardError> for Decision {
+    fn from(error: GuardError) -> Self {
+        Decision::Deny(error.into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuardError;
+    use crate::test_helper::TestClient;
+    use crate::{action, GuardRouter, OnGuard};
+    use axum::response::{IntoResponse, Response};
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct SsoGuard;
+
+    impl OnGuard for SsoGuard {
+        async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
+            Err(GuardError::sso_redirect("https://sso.example.com/login").into_response())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sso_redirect_307() {
+        let router = GuardRouter::new("my:test", Arc::new(SsoGuard))
+            .route("/test", action::post("action1", handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.post("/test").await;
+        // 307 tells the client to post again at the location
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            response.headers()["location"],
+            "https://sso.example.com/login"
+        );
+    }
+
+    async fn handler() {}
+}
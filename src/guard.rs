@@ -1,18 +1,1938 @@
-use axum::response::Response;
+use crate::{GuardContext, GuardEntry, Requirement};
+use axum::{
+    body::Bytes,
+    extract::{FromRequestParts, Request},
+    http::{header, response::Parts, Extensions, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use std::{
+    any, collections::BTreeMap, fmt, future::Future, marker::PhantomData, sync::Arc, time::Duration,
+};
+
+/// The decision of a guard for a request
+#[derive(Debug)]
+pub enum Decision {
+    /// Allow the request to reach the handler
+    Allow,
+    /// Allow the request and skip the next guards of a [`chain`]
+    ForceAllow,
+    /// Deny the request with the response
+    Deny(Response),
+    /// The caller is not authenticated, respond `401 Unauthorized`
+    /// with the challenge in the `WWW-Authenticate` header,
+    /// like `Bearer realm="api"`
+    Challenge(String),
+    /// The request waits for an approval, respond `202 Accepted`
+    /// with the status url in the `Location` header
+    Pending(String),
+    /// Abstain from the decision and let another guard layer decide
+    ///
+    /// The request passes this layer like `Allow`, and a [`GuardSkipped`]
+    /// marker is added to the request extensions for the inner layers and
+    /// the handler, and to the response extensions for the outer layers.
+    Skip,
+    /// The guard could not decide, like when the policy service errors or
+    /// times out, it is handled with the [`FailMode`] of the router
+    Error(String),
+}
+
+/// How a [`Decision::Error`] of the guard is handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailMode {
+    /// Allow the request, keeping the service available while the guard
+    /// backend is down
+    Open,
+    /// Deny the request with `503 Service Unavailable`
+    #[default]
+    Closed,
+}
+
+/// How a guarded request is done, passed to `OnGuard::on_complete`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// A response is returned, by the guard or by the handler
+    Responded(StatusCode),
+    /// The inner service failed
+    Failed,
+    /// The request future is dropped before the response
+    Cancelled,
+}
+
+/// The marker of a guard layer that returned [`Decision::Skip`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuardSkipped {
+    pub resource: String,
+    pub action: String,
+}
+
+/// The check which denied a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DenyKind {
+    /// The authentication, `on_authenticate`
+    Authentication,
+    /// The roles check, `on_roles` or `on_roles_for_request`
+    Roles,
+    /// The resource and action check
+    Resource,
+}
+
+impl DenyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DenyKind::Authentication => "authentication",
+            DenyKind::Roles => "roles",
+            DenyKind::Resource => "resource",
+        }
+    }
+}
+
+/// A denied request, passed to [`OnGuard::on_denied`] and `GuardRouter::on_deny`
+/// to build the response
+#[derive(Debug)]
+pub struct DenyReason {
+    pub kind: DenyKind,
+    pub resource: String,
+    pub action: String,
+    /// The request id, when the router is built with `with_request_id`
+    pub request_id: Option<String>,
+    /// The response built by the guard
+    pub response: Response,
+}
+
+impl DenyReason {
+    /// Replace the response with the one built by the `on_denied` of a guard
+    pub(crate) fn pass(self, on_denied: impl FnOnce(Self) -> Response) -> Self {
+        let (kind, resource, action) = (self.kind, self.resource.clone(), self.action.clone());
+        let request_id = self.request_id.clone();
+        let response = on_denied(self);
+        Self {
+            kind,
+            resource,
+            action,
+            request_id,
+            response,
+        }
+    }
+}
+
+pub(crate) type DenyFn = dyn Fn(DenyReason) -> Response + Send + Sync;
+
+/// The deny response builder shared by the layers of a router
+#[derive(Clone)]
+pub(crate) struct OnDeny(pub(crate) Arc<DenyFn>);
+
+impl fmt::Debug for OnDeny {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnDeny").finish_non_exhaustive()
+    }
+}
+
+impl Decision {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Decision::Allow | Decision::ForceAllow => "allow",
+            Decision::Deny(_) => "deny",
+            Decision::Challenge(_) => "challenge",
+            Decision::Pending(_) => "pending",
+            Decision::Error(_) => "error",
+            Decision::Skip => "skip",
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn into_result(self) -> Result<(), Response> {
+        match self {
+            Decision::Allow | Decision::ForceAllow | Decision::Skip => Ok(()),
+            Decision::Deny(response) => Err(response),
+            Decision::Challenge(challenge) => Err((
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, challenge)],
+            )
+                .into_response()),
+            Decision::Pending(location) => {
+                Err((StatusCode::ACCEPTED, [(header::LOCATION, location)]).into_response())
+            }
+            Decision::Error(_) => Err(StatusCode::SERVICE_UNAVAILABLE.into_response()),
+        }
+    }
+}
+
+impl From<Result<(), Response>> for Decision {
+    fn from(result: Result<(), Response>) -> Self {
+        match result {
+            Ok(()) => Decision::Allow,
+            Err(response) => Decision::Deny(response),
+        }
+    }
+}
 
 /// A guard trati to run before a handler process
 ///
-#[allow(async_fn_in_trait)]
-pub trait OnGuard {
+/// The methods return `Send` futures, so they are awaited on the runtime
+/// instead of blocking it. Implementations can still use `async fn`.
+///
+/// It is implemented for `&G`, `Box<G>` and `Arc<G>` of a guard, so a shared
+/// guard is passed to the combinators as it is.
+pub trait OnGuard: Send + Sync {
     /// Check the handler with resource and action
     ///  If it is not allowed, return error response
-    async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
-        Ok(())
+    fn on_guard(
+        &self,
+        _resource: &str,
+        _action: &str,
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Authenticate the caller before the roles and the resource checks
+    /// If it fails, return the `WWW-Authenticate` challenge, like
+    /// `Bearer realm="api"`, it is responded with `401 Unauthorized`
+    fn on_authenticate(
+        &self,
+        _ctx: &GuardContext<'_>,
+    ) -> impl Future<Output = Result<(), String>> + Send {
+        async { Ok(()) }
     }
 
     /// Check the handler with given roles
     /// If it is not allowed, return error response
-    async fn on_roles(&self, _roles: &[String]) -> Result<(), Response> {
+    fn on_roles(&self, _roles: &[String]) -> impl Future<Output = Result<(), Response>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Check the static roles with the resource and action of the route,
+    /// like to log or vary the check per endpoint
+    /// The default forwards to `on_roles`. With `GuardRouter::cached`, the
+    /// roles passed by an outer layer are not checked again.
+    fn on_roles_for(
+        &self,
+        _resource: &str,
+        _action: &str,
+        roles: &[String],
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        self.on_roles(roles)
+    }
+
+    /// Check the roles derived from the request, like the roles of a token
+    /// It only runs when the router has no static roles, in place of `on_roles`,
+    /// and before the resource and action check
+    fn on_roles_for_request(
+        &self,
+        _ctx: &GuardContext<'_>,
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Check the handler with resource, action and the matched path parameters
+    /// The default forwards to `on_guard`
+    fn on_guard_params(
+        &self,
+        resource: &str,
+        action: &str,
+        _params: &BTreeMap<String, String>,
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        self.on_guard(resource, action)
+    }
+
+    /// Check the handler with the request context
+    /// It runs after the roles check, the default forwards to `on_guard_params`
+    fn on_request(
+        &self,
+        ctx: &GuardContext<'_>,
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        async move {
+            self.on_guard_params(ctx.resource(), ctx.action(), ctx.params())
+                .await
+        }
+    }
+
+    /// Decide the request with the request context
+    /// It runs after the roles check, the default forwards to `on_request`
+    fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> impl Future<Output = Decision> + Send {
+        async move { self.on_request(ctx).await.into() }
+    }
+
+    /// Pick the action to authorize among the candidates, returning its index
+    /// It runs in place of `on_guard_decision` for the actions registered with
+    /// candidates, the default decides every candidate in order with
+    /// `on_guard_decision` and the first allowed one wins
+    fn on_guard_candidates(
+        &self,
+        ctx: &GuardContext<'_>,
+        candidates: &[String],
+    ) -> impl Future<Output = Result<usize, Response>> + Send {
+        async move {
+            let mut denied = None;
+            for (index, candidate) in candidates.iter().enumerate() {
+                let candidate_ctx = ctx.with_action(candidate);
+                match self.on_guard_decision(&candidate_ctx).await.into_result() {
+                    Ok(()) => {
+                        ctx.extend(candidate_ctx);
+                        return Ok(index);
+                    }
+                    Err(response) => denied = Some(response),
+                }
+            }
+            Err(denied.unwrap_or_else(|| StatusCode::FORBIDDEN.into_response()))
+        }
+    }
+
+    /// Called when the guard returns [`Decision::Error`], like to report the
+    /// failures of the policy service, before the [`FailMode`] is applied
+    fn on_error(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _error: &str,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Called once the request is allowed, before it reaches the handler,
+    /// like to meter the calls per resource and action
+    /// The roles are the static roles of the route, empty when the guard
+    /// derives them from the request. It is not called when the guard skips.
+    /// The request id is the one of `GuardRouter::with_request_id`, if set.
+    fn on_allowed(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _roles: &[String],
+        _request_id: Option<&str>,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Rewrite the allowed request before it reaches the handler, like to
+    /// remove an internal header or insert an extension.
+    ///
+    /// It runs after the roles and the resource and action checks, before
+    /// `on_allowed`. An error response denies the request like the resource
+    /// check, through `on_denied`. The checked resource and action are in
+    /// the `GuardInfo` of the request extensions.
+    fn on_request_mut(
+        &self,
+        _request: &mut Request,
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Observe the handler response of an allowed request, like for audit logs
+    /// Only the response head is passed, the body is left to the client.
+    /// It is not called for the denied requests. The request id is the one of
+    /// `GuardRouter::with_request_id`, if set.
+    fn on_response(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _response: &Parts,
+        _request_id: Option<&str>,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Transform the buffered body of an allowed response, like to redact the
+    /// `email` field of a user for the callers who are not admins
+    /// It is only called when the router buffers the responses with
+    /// `GuardRouter::filter_response`. The request extensions hold the values
+    /// inserted by the guard, like the roles of the caller.
+    fn on_response_body(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _request: &Extensions,
+        _response: &Parts,
+        body: Bytes,
+    ) -> impl Future<Output = Bytes> + Send {
+        async { body }
+    }
+
+    /// Record the final status and latency of every response of the layer,
+    /// like for a compliance audit log
+    /// Unlike `on_response`, it is also called for the denied requests, after
+    /// the deny response is built. It is not called when the inner service
+    /// fails or the request is cancelled. The request id is the one of
+    /// `GuardRouter::with_request_id`, also sent in the response, if set.
+    fn on_audit(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _status: StatusCode,
+        _elapsed: Duration,
+        _request_id: Option<&str>,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Build the response of a denied request in one place, like a JSON
+    /// error envelope with the trace id, returns the guard response by default
+    ///
+    /// It is called for every failed check before `GuardRouter::on_deny`,
+    /// but not in the shadow mode.
+    fn on_denied(&self, reason: DenyReason) -> Response {
+        reason.response
+    }
+
+    /// Called once the request is done, also when it is cancelled,
+    /// like when the client disconnects
+    ///
+    /// It is called when the request future is dropped, so it can not await.
+    fn on_complete(&self, _resource: &str, _action: &str, _outcome: Outcome) {}
+
+    /// Check the guarded endpoints when the router is built, like that every
+    /// action exists in the policy store, an error panics with its message
+    ///
+    /// The manifest is the one of `GuardRouter::manifest`, with the actions
+    /// of the nested routers.
+    fn validate(&self, _manifest: &[GuardEntry]) -> Result<(), String> {
         Ok(())
     }
 }
+
+/// Chain two guards, the second guard only runs when the first one allows
+/// or skips
+///
+/// If the first guard returns [`Decision::ForceAllow`], the request is allowed
+/// without asking the second guard, which is useful for admin overrides.
+/// The roles are checked by both guards in order.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::{guard, GuardRouter};
+///
+///  let router = GuardRouter::new("my:router:admin", Arc::new(guard::chain(AdminGuard, MyGuard)))
+///     .action("my:get", "/admin", get(handler));
+/// ```
+pub fn chain<A, B>(first: A, second: B) -> Chain<A, B>
+where
+    A: OnGuard,
+    B: OnGuard,
+{
+    Chain { first, second }
+}
+
+/// A guard created by [`chain`]
+#[derive(Clone, Debug)]
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> OnGuard for Chain<A, B>
+where
+    A: OnGuard,
+    B: OnGuard,
+{
+    async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+        match self.first.on_guard_decision(ctx).await {
+            Decision::Allow => match self.second.on_guard_decision(ctx).await {
+                Decision::Skip => Decision::Allow,
+                decision => decision,
+            },
+            Decision::Skip => self.second.on_guard_decision(ctx).await,
+            decision => decision,
+        }
+    }
+
+    forward_hooks!(OnGuard, [first, second]; checks, observers);
+}
+
+/// Combine guards, the request is allowed when every guard allows
+///
+/// The guards run in order and the first deny is returned without running
+/// the next ones. The roles are checked the same way. A guard returning
+/// [`Decision::ForceAllow`] allows the request at once, and the guards
+/// returning [`Decision::Skip`] are ignored, so the result is `Skip` when
+/// all of them skip.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::{guard, GuardRouter};
+///
+///  let router = GuardRouter::new("my:router:user", Arc::new(guard::all(vec![auth, tenancy])))
+///     .action("my:get", "/user", get(handler));
+/// ```
+pub fn all<G>(guards: Vec<G>) -> All<G>
+where
+    G: OnGuard,
+{
+    All { guards }
+}
+
+/// A guard created by [`all`]
+#[derive(Clone, Debug)]
+pub struct All<G> {
+    guards: Vec<G>,
+}
+
+impl<G> OnGuard for All<G>
+where
+    G: OnGuard,
+{
+    async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+        let mut skipped = !self.guards.is_empty();
+        for guard in &self.guards {
+            match guard.on_guard_decision(ctx).await {
+                Decision::Allow => skipped = false,
+                Decision::Skip => {}
+                decision => return decision,
+            }
+        }
+        match skipped {
+            true => Decision::Skip,
+            false => Decision::Allow,
+        }
+    }
+
+    forward_hooks!(OnGuard, (guards); checks, observers);
+}
+
+/// Check a guard as one unit, its authentication, the roles of the route
+/// and its decision, so a combined guard allows the request only when one
+/// of its guards passes all of them
+///
+/// The decision is left out for the layers which do not decide, like the
+/// roles only layers.
+async fn check_unit<G: OnGuard>(guard: &G, ctx: &GuardContext<'_>) -> Decision {
+    if let Err(challenge) = guard.on_authenticate(ctx).await {
+        return Decision::Challenge(challenge);
+    }
+    if let Some(response) = ctx.take_denied() {
+        return Decision::Deny(response);
+    }
+    if let Err(response) = check_roles(guard, ctx).await {
+        return Decision::Deny(response);
+    }
+    match ctx.decides() {
+        true => guard.on_guard_decision(ctx).await,
+        false => Decision::Allow,
+    }
+}
+
+/// Check the roles of the route like the guard layer
+async fn check_roles<G: OnGuard>(guard: &G, ctx: &GuardContext<'_>) -> Result<(), Response> {
+    let policy = ctx.policy();
+    match &policy.roles {
+        Some(roles) => {
+            let checks = match policy.requirement {
+                Requirement::Any => vec![roles.as_slice()],
+                Requirement::All => roles.iter().map(std::slice::from_ref).collect(),
+            };
+            for roles in checks {
+                guard
+                    .on_roles_for(&policy.resource, &policy.action, roles)
+                    .await?;
+            }
+            Ok(())
+        }
+        None => guard.on_roles_for_request(ctx).await,
+    }
+}
+
+/// Authenticate with the result of the units of a combined guard, for the
+/// layers which do not decide, the other denies are returned after it
+fn settle(ctx: &GuardContext<'_>, decision: Decision) -> Result<(), String> {
+    match decision {
+        Decision::Challenge(challenge) => Err(challenge),
+        decision => {
+            if let Err(response) = decision.into_result() {
+                ctx.deny(response);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Combine guards, the request is allowed when one of the guards allows
+///
+/// The guards run in order until one allows, when all of them fail the
+/// last deny is returned. Each guard is checked as one unit, its
+/// authentication, the roles of the route and its decision, so the request
+/// is only allowed when a single guard passes all of them. The guards
+/// returning [`Decision::Skip`] are ignored, so the result is `Skip` when
+/// all of them skip, and a `403` deny when there is no guard.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::{guard, GuardRouter};
+///
+///  let router = GuardRouter::new("my:router:user", Arc::new(guard::any(vec![session, api_key])))
+///     .action("my:get", "/user", get(handler));
+/// ```
+pub fn any<G>(guards: Vec<G>) -> Any<G>
+where
+    G: OnGuard,
+{
+    Any { guards }
+}
+
+/// A guard created by [`any`]
+#[derive(Clone, Debug)]
+pub struct Any<G> {
+    guards: Vec<G>,
+}
+
+impl<G> OnGuard for Any<G>
+where
+    G: OnGuard,
+{
+    // the roles are checked with the authentication of each guard
+    async fn on_authenticate(&self, ctx: &GuardContext<'_>) -> Result<(), String> {
+        match ctx.decides() {
+            true => Ok(()),
+            false => settle(ctx, OnGuard::on_guard_decision(self, ctx).await),
+        }
+    }
+
+    async fn on_roles_for(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _roles: &[String],
+    ) -> Result<(), Response> {
+        Ok(())
+    }
+
+    async fn on_roles_for_request(&self, _ctx: &GuardContext<'_>) -> Result<(), Response> {
+        Ok(())
+    }
+
+    async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+        let mut last = None;
+        for guard in &self.guards {
+            match check_unit(guard, ctx).await {
+                decision @ (Decision::Allow | Decision::ForceAllow) => return decision,
+                Decision::Skip => {}
+                decision => last = Some(decision),
+            }
+        }
+        match last {
+            Some(decision) => decision,
+            None if self.guards.is_empty() => Decision::Deny(StatusCode::FORBIDDEN.into_response()),
+            None => Decision::Skip,
+        }
+    }
+
+    forward_hooks!(OnGuard, (guards); observers);
+}
+
+/// Combinators to compose guards with method calls
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::guard::GuardExt;
+///
+///  // the ip allowlist and the token scope must both pass
+///  let guard = IpAllowlist::new(&["10.0.0.0/8"]).and(ScopeGuard::new("orders:write"));
+///  let router = GuardRouter::new("my:router:order", Arc::new(guard))
+///     .action("my:create", "/orders", post(handler));
+/// ```
+pub trait GuardExt: OnGuard + Sized {
+    /// Allow when both guards allow, like [`chain`]
+    ///
+    /// The first deny is returned, so the second guard only runs when the
+    /// first one allows.
+    fn and<B>(self, other: B) -> Chain<Self, B>
+    where
+        B: OnGuard,
+    {
+        chain(self, other)
+    }
+
+    /// Allow when one of the guards allows, like [`any`]
+    ///
+    /// Each guard is checked as one unit, its authentication, the roles of
+    /// the route and its decision, like [`any`]. The second guard only runs
+    /// when the first one does not allow, and the deny of the second guard
+    /// is returned when both deny.
+    fn or<B>(self, other: B) -> Or<Self, B>
+    where
+        B: OnGuard,
+    {
+        Or {
+            first: self,
+            second: other,
+        }
+    }
+
+    /// Invert the decision, an allow is denied with `403` and a `401` or
+    /// `403` deny is allowed
+    ///
+    /// The other decisions and denies are kept, so an unauthenticated caller,
+    /// a failed guard or a `5xx` deny is not allowed. The authentication and
+    /// the roles are checked by the guard as they are, and its other hooks
+    /// are called as they are.
+    fn not(self) -> Not<Self> {
+        Not { guard: self }
+    }
+}
+
+impl<G> GuardExt for G where G: OnGuard {}
+
+/// A guard created by [`GuardExt::or`]
+#[derive(Clone, Debug)]
+pub struct Or<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> OnGuard for Or<A, B>
+where
+    A: OnGuard,
+    B: OnGuard,
+{
+    // the roles are checked with the authentication of each guard
+    async fn on_authenticate(&self, ctx: &GuardContext<'_>) -> Result<(), String> {
+        match ctx.decides() {
+            true => Ok(()),
+            false => settle(ctx, OnGuard::on_guard_decision(self, ctx).await),
+        }
+    }
+
+    async fn on_roles_for(
+        &self,
+        _resource: &str,
+        _action: &str,
+        _roles: &[String],
+    ) -> Result<(), Response> {
+        Ok(())
+    }
+
+    async fn on_roles_for_request(&self, _ctx: &GuardContext<'_>) -> Result<(), Response> {
+        Ok(())
+    }
+
+    async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+        let first = check_unit(&self.first, ctx).await;
+        if let Decision::Allow | Decision::ForceAllow = first {
+            return first;
+        }
+        match check_unit(&self.second, ctx).await {
+            Decision::Skip => first,
+            decision => decision,
+        }
+    }
+
+    forward_hooks!(OnGuard, [first, second]; observers);
+}
+
+/// A guard created by [`GuardExt::not`]
+#[derive(Clone, Debug)]
+pub struct Not<G> {
+    guard: G,
+}
+
+impl<G> OnGuard for Not<G>
+where
+    G: OnGuard,
+{
+    async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+        match self.guard.on_guard_decision(ctx).await {
+            Decision::Allow | Decision::ForceAllow => {
+                Decision::Deny(StatusCode::FORBIDDEN.into_response())
+            }
+            Decision::Deny(response)
+                if matches!(
+                    response.status(),
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+                ) =>
+            {
+                Decision::Allow
+            }
+            decision => decision,
+        }
+    }
+
+    forward_hooks!(OnGuard, [guard]; checks, observers);
+}
+
+/// A guard resolving the caller identity while it authorizes the request
+///
+/// Wrap it with [`identify`] to use it in a router, the identity returned on
+/// success is inserted into the request extensions, so handlers can use
+/// `Extension<Self::Identity>`. The roles are still checked by its `OnGuard`
+/// implementation, which can be empty.
+pub trait Authorize: OnGuard {
+    type Identity: Clone + Send + Sync + 'static;
+
+    /// Authorize the request and return the caller identity
+    fn authorize(
+        &self,
+        ctx: &GuardContext<'_>,
+    ) -> impl Future<Output = Result<Self::Identity, Response>> + Send;
+}
+
+/// Use an [`Authorize`] guard in a router
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::guard::{self, Authorize};
+///
+///  #[derive(Clone)]
+///  struct User { id: String }
+///
+///  impl OnGuard for MyGuard {}
+///
+///  impl Authorize for MyGuard {
+///      type Identity = User;
+///
+///      async fn authorize(&self, ctx: &GuardContext<'_>) -> Result<User, Response> {
+///          // resolve the user and check the permission
+///      }
+///  }
+///
+///  async fn handler(Extension(user): Extension<User>) {}
+///
+///  let router = GuardRouter::new("my:router:user", Arc::new(guard::identify(MyGuard)))
+///     .action("my:get", "/user", get(handler));
+/// ```
+pub fn identify<A>(guard: A) -> Identify<A>
+where
+    A: Authorize,
+{
+    Identify { guard }
+}
+
+/// A guard created by [`identify`]
+#[derive(Clone, Debug)]
+pub struct Identify<A> {
+    guard: A,
+}
+
+impl<A> OnGuard for Identify<A>
+where
+    A: Authorize,
+{
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        let identity = self.guard.authorize(ctx).await?;
+        ctx.insert(identity);
+        Ok(())
+    }
+
+    forward_hooks!(OnGuard, [guard]; checks, observers);
+}
+
+/// A guard deciding with the context of an axum extractor, like `Claims`
+///
+/// Wrap it with [`extract`] to use it in a router, the extractor runs with
+/// [`GuardContext::extract_with_state`] before the check, and its rejection
+/// denies the request. The roles are still checked by its `OnGuard`
+/// implementation, which can be empty.
+///
+/// The extractor gets the state `S`, `()` by default. Another state is read
+/// with [`GuardContext::router_state`], so the router is built with
+/// `GuardRouter::with_state`, otherwise the requests fail with 500.
+pub trait ExtractGuard<S = ()>: OnGuard {
+    type Context: FromRequestParts<S> + Send;
+
+    /// Check the request with the extracted context
+    fn on_extracted(
+        &self,
+        ctx: &GuardContext<'_>,
+        context: Self::Context,
+    ) -> impl Future<Output = Result<(), Response>> + Send;
+}
+
+/// Use an [`ExtractGuard`] in a router
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::guard::{self, ExtractGuard};
+///
+///  impl OnGuard for ScopeGuard {}
+///
+///  impl ExtractGuard for ScopeGuard {
+///      type Context = Claims;
+///
+///      async fn on_extracted(&self, ctx: &GuardContext<'_>, claims: Claims) -> Result<(), Response> {
+///          match claims.scopes.iter().any(|scope| scope == ctx.action()) {
+///              true => Ok(()),
+///              false => Err(StatusCode::FORBIDDEN.into_response()),
+///          }
+///      }
+///  }
+///
+///  let router = GuardRouter::new("my:router:order", Arc::new(guard::extract(ScopeGuard)))
+///     .action("orders:read", "/orders", get(handler));
+/// ```
+pub fn extract<E, S>(guard: E) -> Extract<E, S>
+where
+    E: ExtractGuard<S>,
+{
+    Extract {
+        guard,
+        state: PhantomData,
+    }
+}
+
+/// A guard created by [`extract`]
+pub struct Extract<E, S = ()> {
+    guard: E,
+    state: PhantomData<fn() -> S>,
+}
+
+impl<E: Clone, S> Clone for Extract<E, S> {
+    fn clone(&self) -> Self {
+        Self {
+            guard: self.guard.clone(),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<E: fmt::Debug, S> fmt::Debug for Extract<E, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extract")
+            .field("guard", &self.guard)
+            .finish()
+    }
+}
+
+impl<E, S> OnGuard for Extract<E, S>
+where
+    E: ExtractGuard<S>,
+    S: Clone + Send + Sync + 'static,
+{
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        // the unit state is always there, another one is given by the router
+        let state = ctx
+            .router_state::<S>()
+            .or_else(|| (&() as &dyn any::Any).downcast_ref::<S>());
+        let Some(state) = state else {
+            log::error!(
+                "Extract: no router state for resource={} action={}",
+                ctx.resource(),
+                ctx.action()
+            );
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        };
+        let context = ctx
+            .extract_with_state::<E::Context, S>(state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        self.guard.on_extracted(ctx, context).await
+    }
+
+    forward_hooks!(OnGuard, [guard]; checks, observers);
+}
+
+/// Create a guard from a closure checking the resource and action, like
+/// `tower::service_fn`
+///
+/// The closure gets the owned resource and action, so its future can be
+/// `async move`. It is used as `OnGuard::on_guard`, the roles pass.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::guard_fn;
+///
+///  let guard = guard_fn(|resource, action| async move {
+///      match action.as_str() {
+///          "my:delete" => Err(StatusCode::FORBIDDEN.into_response()),
+///          _ => Ok(()),
+///      }
+///  });
+///  let router = GuardRouter::new("my:router:user", Arc::new(guard))
+///     .action("my:get", "/user", get(handler));
+/// ```
+pub fn guard_fn<F, Fut>(f: F) -> GuardFn<F>
+where
+    F: Fn(String, String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), Response>> + Send,
+{
+    GuardFn { f }
+}
+
+/// A guard created by [`guard_fn`]
+#[derive(Clone)]
+pub struct GuardFn<F> {
+    f: F,
+}
+
+impl<F> fmt::Debug for GuardFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuardFn").finish_non_exhaustive()
+    }
+}
+
+impl<F, Fut> OnGuard for GuardFn<F>
+where
+    F: Fn(String, String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), Response>> + Send,
+{
+    fn on_guard(
+        &self,
+        resource: &str,
+        action: &str,
+    ) -> impl Future<Output = Result<(), Response>> + Send {
+        (self.f)(resource.to_string(), action.to_string())
+    }
+}
+
+/// A guard selected at runtime, like a permissive guard in development
+///
+/// `OnGuard` returns `impl Future`, so `Arc<dyn OnGuard>` does not compile.
+/// `DynGuard` is the supported replacement: it boxes the futures of any
+/// guard, forwards all of its hooks, and the routers of different guards
+/// become the same `GuardRouter<DynGuard>` type. A guard already in an `Arc`
+/// is shared with `DynGuard::from`.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::guard::DynGuard;
+///
+///  let guard = match dev {
+///      true => DynGuard::new(PermissiveGuard),
+///      false => DynGuard::new(MyGuard),
+///  };
+///  let router = GuardRouter::new("my:router:user", Arc::new(guard))
+///     .action("my:get", "/user", get(handler));
+/// ```
+#[derive(Clone)]
+pub struct DynGuard {
+    guard: Arc<dyn ErasedGuard>,
+}
+
+impl DynGuard {
+    /// Erase the type of a guard, in place of `Arc<dyn OnGuard>`
+    pub fn new<G>(guard: G) -> Self
+    where
+        G: OnGuard + 'static,
+    {
+        Self {
+            guard: Arc::new(guard),
+        }
+    }
+}
+
+/// Share a guard which is already in an `Arc`, like a guard with counters
+impl<G> From<Arc<G>> for DynGuard
+where
+    G: OnGuard + 'static,
+{
+    fn from(guard: Arc<G>) -> Self {
+        Self { guard }
+    }
+}
+
+impl fmt::Debug for DynGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynGuard").finish_non_exhaustive()
+    }
+}
+
+/// The object-safe `OnGuard` boxing the futures, for [`DynGuard`]
+trait ErasedGuard: Send + Sync {
+    fn on_guard<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+    ) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_authenticate<'a>(
+        &'a self,
+        ctx: &'a GuardContext<'_>,
+    ) -> BoxFuture<'a, Result<(), String>>;
+    fn on_roles<'a>(&'a self, roles: &'a [String]) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_roles_for<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        roles: &'a [String],
+    ) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_roles_for_request<'a>(
+        &'a self,
+        ctx: &'a GuardContext<'_>,
+    ) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_guard_params<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        params: &'a BTreeMap<String, String>,
+    ) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_request<'a>(&'a self, ctx: &'a GuardContext<'_>) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_guard_decision<'a>(&'a self, ctx: &'a GuardContext<'_>) -> BoxFuture<'a, Decision>;
+    fn on_guard_candidates<'a>(
+        &'a self,
+        ctx: &'a GuardContext<'_>,
+        candidates: &'a [String],
+    ) -> BoxFuture<'a, Result<usize, Response>>;
+    fn on_error<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        error: &'a str,
+    ) -> BoxFuture<'a, ()>;
+    fn on_allowed<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        roles: &'a [String],
+        request_id: Option<&'a str>,
+    ) -> BoxFuture<'a, ()>;
+    fn on_request_mut<'a>(
+        &'a self,
+        request: &'a mut Request,
+    ) -> BoxFuture<'a, Result<(), Response>>;
+    fn on_response<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        response: &'a Parts,
+        request_id: Option<&'a str>,
+    ) -> BoxFuture<'a, ()>;
+    fn on_response_body<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        request: &'a Extensions,
+        response: &'a Parts,
+        body: Bytes,
+    ) -> BoxFuture<'a, Bytes>;
+    fn on_audit<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        status: StatusCode,
+        elapsed: Duration,
+        request_id: Option<&'a str>,
+    ) -> BoxFuture<'a, ()>;
+    fn on_denied(&self, reason: DenyReason) -> Response;
+    fn on_complete(&self, resource: &str, action: &str, outcome: Outcome);
+    fn validate(&self, manifest: &[GuardEntry]) -> Result<(), String>;
+}
+
+impl<G> ErasedGuard for G
+where
+    G: OnGuard,
+{
+    fn on_guard<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+    ) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_guard(self, resource, action))
+    }
+
+    fn on_authenticate<'a>(
+        &'a self,
+        ctx: &'a GuardContext<'_>,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(OnGuard::on_authenticate(self, ctx))
+    }
+
+    fn on_roles<'a>(&'a self, roles: &'a [String]) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_roles(self, roles))
+    }
+
+    fn on_roles_for<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        roles: &'a [String],
+    ) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_roles_for(self, resource, action, roles))
+    }
+
+    fn on_roles_for_request<'a>(
+        &'a self,
+        ctx: &'a GuardContext<'_>,
+    ) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_roles_for_request(self, ctx))
+    }
+
+    fn on_guard_params<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        params: &'a BTreeMap<String, String>,
+    ) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_guard_params(self, resource, action, params))
+    }
+
+    fn on_request<'a>(&'a self, ctx: &'a GuardContext<'_>) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_request(self, ctx))
+    }
+
+    fn on_guard_decision<'a>(&'a self, ctx: &'a GuardContext<'_>) -> BoxFuture<'a, Decision> {
+        Box::pin(OnGuard::on_guard_decision(self, ctx))
+    }
+
+    fn on_guard_candidates<'a>(
+        &'a self,
+        ctx: &'a GuardContext<'_>,
+        candidates: &'a [String],
+    ) -> BoxFuture<'a, Result<usize, Response>> {
+        Box::pin(OnGuard::on_guard_candidates(self, ctx, candidates))
+    }
+
+    fn on_error<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        error: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(OnGuard::on_error(self, resource, action, error))
+    }
+
+    fn on_allowed<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        roles: &'a [String],
+        request_id: Option<&'a str>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(OnGuard::on_allowed(
+            self, resource, action, roles, request_id,
+        ))
+    }
+
+    fn on_request_mut<'a>(
+        &'a self,
+        request: &'a mut Request,
+    ) -> BoxFuture<'a, Result<(), Response>> {
+        Box::pin(OnGuard::on_request_mut(self, request))
+    }
+
+    fn on_response<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        response: &'a Parts,
+        request_id: Option<&'a str>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(OnGuard::on_response(
+            self, resource, action, response, request_id,
+        ))
+    }
+
+    fn on_response_body<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        request: &'a Extensions,
+        response: &'a Parts,
+        body: Bytes,
+    ) -> BoxFuture<'a, Bytes> {
+        Box::pin(OnGuard::on_response_body(
+            self, resource, action, request, response, body,
+        ))
+    }
+
+    fn on_audit<'a>(
+        &'a self,
+        resource: &'a str,
+        action: &'a str,
+        status: StatusCode,
+        elapsed: Duration,
+        request_id: Option<&'a str>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(OnGuard::on_audit(
+            self, resource, action, status, elapsed, request_id,
+        ))
+    }
+
+    fn on_denied(&self, reason: DenyReason) -> Response {
+        OnGuard::on_denied(self, reason)
+    }
+
+    fn on_complete(&self, resource: &str, action: &str, outcome: Outcome) {
+        OnGuard::on_complete(self, resource, action, outcome)
+    }
+
+    fn validate(&self, manifest: &[GuardEntry]) -> Result<(), String> {
+        OnGuard::validate(self, manifest)
+    }
+}
+
+impl OnGuard for DynGuard {
+    forward_hooks!(
+        ErasedGuard,
+        [*guard];
+        on_guard,
+        checks,
+        on_guard_params,
+        on_request,
+        on_guard_decision,
+        on_guard_candidates,
+        observers,
+    );
+}
+
+/// An ordered pipeline of different guards, like authentication, then the
+/// tenant check, then the permission check
+///
+/// The stages run like [`all`]: a stage denying the request or returning
+/// [`Decision::ForceAllow`] stops the pipeline, and a stage can pass values
+/// to the next ones with [`GuardContext::insert`] and [`GuardContext::get`].
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::guard::GuardStack;
+///
+///  let stack = GuardStack::new()
+///     .push(AuthnGuard)
+///     .push(TenantGuard)
+///     .push(PermissionGuard);
+///  let router = GuardRouter::new("my:router:order", Arc::new(stack))
+///     .action("my:list", "/orders", get(handler));
+/// ```
+#[derive(Clone, Debug)]
+pub struct GuardStack {
+    stages: All<DynGuard>,
+}
+
+impl GuardStack {
+    /// Create an empty stack, which allows every request like an empty [`all`]
+    pub fn new() -> Self {
+        Self {
+            stages: all(Vec::new()),
+        }
+    }
+
+    /// Add a stage after the current ones
+    pub fn push<G>(mut self, guard: G) -> Self
+    where
+        G: OnGuard + 'static,
+    {
+        self.stages.guards.push(DynGuard::new(guard));
+        self
+    }
+}
+
+impl Default for GuardStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OnGuard for GuardStack {
+    forward_hooks!(OnGuard, [stages]; checks, on_guard_decision, observers);
+}
+
+/// Forward every hook of a pointer to the guard it points to
+macro_rules! impl_pointer_guard {
+    ($($pointer:ty),+) => {$(
+        impl<G> OnGuard for $pointer
+        where
+            G: OnGuard,
+        {
+            forward_hooks!(
+                OnGuard,
+                *;
+                on_guard,
+                checks,
+                on_guard_params,
+                on_request,
+                on_guard_decision,
+                on_guard_candidates,
+                observers,
+            );
+        }
+    )+};
+}
+
+impl_pointer_guard!(&G, Box<G>, Arc<G>);
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        all, any, chain, extract, guard_fn, identify, Authorize, Decision, DynGuard, ExtractGuard,
+        GuardExt, GuardSkipped, GuardStack, OnGuard,
+    };
+    use crate::test_helper::{TestClient, TestGuard};
+    use crate::{GuardContext, GuardRouter};
+    use axum::{
+        async_trait,
+        extract::FromRequestParts,
+        http::{request::Parts, StatusCode},
+        response::{IntoResponse, Response},
+        routing::get,
+        Extension,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct AdminGuard(bool);
+
+    impl OnGuard for AdminGuard {
+        async fn on_guard_decision(&self, _ctx: &GuardContext<'_>) -> Decision {
+            match self.0 {
+                true => Decision::ForceAllow,
+                false => Decision::Allow,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_force_allow() {
+        let deny = TestGuard::new();
+        let guard = chain(AdminGuard(true), deny.clone());
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+        assert!(deny.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chain_allow_runs_next() {
+        let deny = TestGuard::new();
+        let guard = chain(AdminGuard(false), deny.clone());
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(deny.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[derive(Clone)]
+    struct StatusGuard(StatusCode, Arc<Mutex<Vec<u16>>>);
+
+    impl OnGuard for StatusGuard {
+        async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
+            self.1.lock().unwrap().push(self.0.as_u16());
+            match self.0 {
+                StatusCode::OK => Ok(()),
+                status => Err(status.into_response()),
+            }
+        }
+    }
+
+    fn client<G: OnGuard + Clone + 'static>(guard: G) -> TestClient {
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("action1", "/test", get(handler))
+            .build();
+        TestClient::new(router)
+    }
+
+    #[tokio::test]
+    async fn test_all_short_circuit() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let guard = |status| StatusGuard(status, calls.clone());
+
+        let status = client(all(vec![guard(StatusCode::OK), guard(StatusCode::OK)]))
+            .get("/test")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(*calls.lock().unwrap(), vec![200, 200]);
+
+        calls.lock().unwrap().clear();
+        let guards = vec![
+            guard(StatusCode::OK),
+            guard(StatusCode::UNAUTHORIZED),
+            guard(StatusCode::NOT_FOUND),
+        ];
+        let status = client(all(guards)).get("/test").await.status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(*calls.lock().unwrap(), vec![200, 401]);
+    }
+
+    #[tokio::test]
+    async fn test_any_fallback_error() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let guard = |status| StatusGuard(status, calls.clone());
+
+        let guards = vec![
+            guard(StatusCode::UNAUTHORIZED),
+            guard(StatusCode::OK),
+            guard(StatusCode::NOT_FOUND),
+        ];
+        let status = client(any(guards)).get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(*calls.lock().unwrap(), vec![401, 200]);
+
+        let guards = vec![
+            guard(StatusCode::UNAUTHORIZED),
+            guard(StatusCode::NOT_FOUND),
+        ];
+        let status = client(any(guards)).get("/test").await.status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[derive(Clone)]
+    struct PhaseGuard {
+        authenticated: bool,
+        roles: bool,
+    }
+
+    impl OnGuard for PhaseGuard {
+        async fn on_authenticate(&self, _ctx: &GuardContext<'_>) -> Result<(), String> {
+            match self.authenticated {
+                true => Ok(()),
+                false => Err("Bearer".to_string()),
+            }
+        }
+
+        async fn on_roles(&self, _roles: &[String]) -> Result<(), Response> {
+            match self.roles {
+                true => Ok(()),
+                false => Err(StatusCode::FORBIDDEN.into_response()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_any_single_guard_passes_all() {
+        let guard = |authenticated, roles| PhaseGuard {
+            authenticated,
+            roles,
+        };
+        // one guard authenticates and the other one has the roles
+        let cases = [
+            (
+                DynGuard::new(any(vec![guard(true, false), guard(false, true)])),
+                401,
+            ),
+            (
+                DynGuard::new(any(vec![guard(false, true), guard(true, false)])),
+                403,
+            ),
+            (
+                DynGuard::new(guard(true, false).or(guard(false, true))),
+                401,
+            ),
+            (
+                DynGuard::new(any(vec![guard(true, false), guard(true, true)])),
+                200,
+            ),
+            (DynGuard::new(guard(false, true).or(guard(true, true))), 200),
+        ];
+        for (guard, status) in cases {
+            for roles_only in [false, true] {
+                let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+                    .roles(&["admin".to_string()])
+                    .action("action1", "/test", get(handler));
+                let router = match roles_only {
+                    true => router.roles_only(),
+                    false => router,
+                };
+                let client = TestClient::new(router.build());
+                assert_eq!(client.get("/test").await.status().as_u16(), status);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_any_empty_denies() {
+        for roles_only in [false, true] {
+            let router = GuardRouter::new("my:test", Arc::new(any(Vec::<TestGuard>::new())))
+                .action("action1", "/test", get(handler));
+            let router = match roles_only {
+                true => router.roles_only(),
+                false => router,
+            };
+            let client = TestClient::new(router.build());
+            assert_eq!(client.get("/test").await.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identify_inserts_identity() {
+        #[derive(Clone)]
+        struct User {
+            id: String,
+        }
+
+        #[derive(Clone)]
+        struct UserGuard;
+
+        impl OnGuard for UserGuard {}
+
+        impl Authorize for UserGuard {
+            type Identity = User;
+
+            async fn authorize(&self, ctx: &GuardContext<'_>) -> Result<User, Response> {
+                let id = ctx
+                    .headers()
+                    .get("x-user")
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or_else(|| StatusCode::UNAUTHORIZED.into_response())?;
+                Ok(User { id: id.to_string() })
+            }
+        }
+
+        async fn user(Extension(user): Extension<User>) -> String {
+            user.id
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(identify(UserGuard)))
+            .action("action1", "/test", get(user))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.get("/test").header("x-user", "alice").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "alice");
+
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_dyn_guard() {
+        let deny = TestGuard::new();
+        let guards = [
+            DynGuard::new(TestGuard::new_with(true, true)),
+            DynGuard::new(deny.clone()),
+        ];
+        let statuses = [StatusCode::OK, StatusCode::FORBIDDEN];
+        for (guard, status) in guards.into_iter().zip(statuses) {
+            let router: GuardRouter<DynGuard> = GuardRouter::new("my:test", Arc::new(guard))
+                .roles(&["admin".to_string()])
+                .action("action1", "/test", get(handler));
+            let client = TestClient::new(router.build());
+            assert_eq!(client.get("/test").await.status(), status);
+        }
+        assert_eq!(deny.calls(), vec!["roles:admin"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_ext() {
+        let allow = || TestGuard::new_with(true, true);
+        let cases = [
+            (DynGuard::new(allow().and(allow())), StatusCode::OK),
+            (
+                DynGuard::new(allow().and(TestGuard::new())),
+                StatusCode::FORBIDDEN,
+            ),
+            (DynGuard::new(TestGuard::new().or(allow())), StatusCode::OK),
+            (
+                DynGuard::new(TestGuard::new().or(TestGuard::new())),
+                StatusCode::FORBIDDEN,
+            ),
+            (DynGuard::new(TestGuard::new().not()), StatusCode::OK),
+            (DynGuard::new(allow().not()), StatusCode::FORBIDDEN),
+        ];
+        for (guard, status) in cases {
+            let router = GuardRouter::new("my:test", Arc::new(guard)).action(
+                "action1",
+                "/test",
+                get(handler),
+            );
+            let client = TestClient::new(router.build());
+            assert_eq!(client.get("/test").await.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_not_keeps_errors() {
+        #[derive(Clone)]
+        struct FailGuard(Option<StatusCode>);
+
+        impl OnGuard for FailGuard {
+            async fn on_guard_decision(&self, _ctx: &GuardContext<'_>) -> Decision {
+                match self.0 {
+                    Some(status) => Decision::Deny(status.into_response()),
+                    None => Decision::Error("the policy service is down".to_string()),
+                }
+            }
+        }
+
+        let cases = [
+            (None, StatusCode::SERVICE_UNAVAILABLE),
+            (Some(StatusCode::BAD_GATEWAY), StatusCode::BAD_GATEWAY),
+            (Some(StatusCode::NOT_FOUND), StatusCode::NOT_FOUND),
+            (Some(StatusCode::UNAUTHORIZED), StatusCode::OK),
+        ];
+        for (status, expected) in cases {
+            let status = client(FailGuard(status).not()).get("/test").await.status();
+            assert_eq!(status, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_or_deny_precedence() {
+        let guard = guard_fn(|_, _| async { Err(StatusCode::UNAUTHORIZED.into_response()) }).or(
+            guard_fn(|_, _| async { Err(StatusCode::FORBIDDEN.into_response()) }),
+        );
+        let router =
+            GuardRouter::new("my:test", Arc::new(guard)).action("action1", "/test", get(handler));
+        let client = TestClient::new(router.build());
+        assert_eq!(client.get("/test").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_stack() {
+        #[derive(Clone)]
+        struct Tenant(String);
+
+        #[derive(Clone)]
+        struct AuthnGuard;
+        impl OnGuard for AuthnGuard {
+            async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+                match ctx.headers().get("x-user").map(|user| user.to_str()) {
+                    Some(Ok("root")) => Decision::ForceAllow,
+                    Some(Ok(user)) => {
+                        let (tenant, _) = user.split_once('/').unwrap_or_default();
+                        ctx.insert(Tenant(tenant.to_string()));
+                        Decision::Allow
+                    }
+                    _ => Decision::Deny(StatusCode::UNAUTHORIZED.into_response()),
+                }
+            }
+        }
+
+        #[derive(Clone)]
+        struct TenantGuard;
+        impl OnGuard for TenantGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                let tenant = ctx.get::<Tenant>().map(|tenant| tenant.0);
+                match tenant.as_deref() == ctx.params().get("tenant").map(String::as_str) {
+                    true => Ok(()),
+                    false => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let permission = TestGuard::new_with(true, true);
+        let stack = GuardStack::new()
+            .push(AuthnGuard)
+            .push(TenantGuard)
+            .push(permission.clone());
+        let router = GuardRouter::new("my:test", Arc::new(stack)).action(
+            "action1",
+            "/:tenant/orders",
+            get(handler),
+        );
+        let client = TestClient::new(router.build());
+        let get = |user: &'static str| client.get("/acme/orders").header("x-user", user);
+
+        assert_eq!(
+            client.get("/acme/orders").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(get("other/bob").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(get("acme/alice").await.status(), StatusCode::OK);
+        assert_eq!(get("root").await.status(), StatusCode::OK);
+        assert_eq!(permission.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_stack_empty_allows() {
+        async fn skipped(skipped: Option<Extension<GuardSkipped>>) -> String {
+            skipped.is_some().to_string()
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(GuardStack::new())).action(
+            "action1",
+            "/test",
+            get(skipped),
+        );
+        let client = TestClient::new(router.build());
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "false");
+    }
+
+    #[tokio::test]
+    async fn test_extract_guard() {
+        struct Claims {
+            scopes: Vec<String>,
+        }
+
+        #[async_trait]
+        impl<S> FromRequestParts<S> for Claims
+        where
+            S: Send + Sync,
+        {
+            type Rejection = StatusCode;
+
+            async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, StatusCode> {
+                let scopes = parts
+                    .headers
+                    .get("x-scopes")
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or(StatusCode::UNAUTHORIZED)?;
+                Ok(Claims {
+                    scopes: scopes.split(' ').map(String::from).collect(),
+                })
+            }
+        }
+
+        #[derive(Clone)]
+        struct ScopeGuard;
+        impl OnGuard for ScopeGuard {}
+        impl ExtractGuard for ScopeGuard {
+            type Context = Claims;
+
+            async fn on_extracted(
+                &self,
+                ctx: &GuardContext<'_>,
+                claims: Claims,
+            ) -> Result<(), Response> {
+                match claims.scopes.iter().any(|scope| scope == ctx.action()) {
+                    true => Ok(()),
+                    false => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(extract(ScopeGuard))).action(
+            "orders:read",
+            "/test",
+            get(handler),
+        );
+        let client = TestClient::new(router.build());
+        assert_eq!(client.get("/test").await.status(), StatusCode::UNAUTHORIZED);
+        let status = client
+            .get("/test")
+            .header("x-scopes", "orders:write")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client
+            .get("/test")
+            .header("x-scopes", "orders:read orders:write")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_extract_guard_state() {
+        #[derive(Clone)]
+        struct AppState {
+            tenant: String,
+        }
+
+        struct Tenant(bool);
+
+        #[async_trait]
+        impl FromRequestParts<AppState> for Tenant {
+            type Rejection = StatusCode;
+
+            async fn from_request_parts(
+                parts: &mut Parts,
+                state: &AppState,
+            ) -> Result<Self, StatusCode> {
+                let tenant = parts
+                    .headers
+                    .get("x-tenant")
+                    .ok_or(StatusCode::UNAUTHORIZED)?;
+                Ok(Tenant(tenant == state.tenant.as_str()))
+            }
+        }
+
+        #[derive(Clone)]
+        struct TenantGuard;
+        impl OnGuard for TenantGuard {}
+        impl ExtractGuard<AppState> for TenantGuard {
+            type Context = Tenant;
+
+            async fn on_extracted(
+                &self,
+                _ctx: &GuardContext<'_>,
+                tenant: Tenant,
+            ) -> Result<(), Response> {
+                match tenant.0 {
+                    true => Ok(()),
+                    false => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let state = AppState {
+            tenant: "acme".to_string(),
+        };
+        let guard = Arc::new(extract(TenantGuard));
+        let router = GuardRouter::new("my:test", guard.clone())
+            .action("action1", "/test", get(handler))
+            .with_state(state);
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::UNAUTHORIZED);
+        let status = client
+            .get("/test")
+            .header("x-tenant", "other")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client
+            .get("/test")
+            .header("x-tenant", "acme")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+
+        // the state is only given by the guard router
+        let router = GuardRouter::<_, AppState>::new("my:test", guard)
+            .action("action1", "/test", get(handler))
+            .build()
+            .with_state(AppState {
+                tenant: "acme".to_string(),
+            });
+        let client = TestClient::new(router);
+        let status = client
+            .get("/test")
+            .header("x-tenant", "acme")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_guard_fn() {
+        let guard = guard_fn(|resource, action| async move {
+            match (resource.as_str(), action.as_str()) {
+                ("my:test", "action1") => Ok(()),
+                _ => Err(StatusCode::FORBIDDEN.into_response()),
+            }
+        });
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("action1", "/test", get(handler))
+            .action("action2", "/test2", get(handler));
+        let client = TestClient::new(router.build());
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test2").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_pointer_guards() {
+        static ADMIN: AdminGuard = AdminGuard(true);
+        let deny = Arc::new(TestGuard::new());
+        let guards = [
+            DynGuard::new(chain(&ADMIN, deny.clone())),
+            DynGuard::new(chain(Box::new(AdminGuard(false)), deny.clone())),
+        ];
+        let statuses = [StatusCode::OK, StatusCode::FORBIDDEN];
+        for (guard, status) in guards.into_iter().zip(statuses) {
+            let router = GuardRouter::new("my:test", Arc::new(guard)).action(
+                "action1",
+                "/test",
+                get(handler),
+            );
+            let client = TestClient::new(router.build());
+            assert_eq!(client.get("/test").await.status(), status);
+        }
+        assert_eq!(deny.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[tokio::test]
+    async fn test_dyn_guard_from_arc() {
+        let shared = Arc::new(TestGuard::new_with(true, true));
+        let guard = DynGuard::from(shared.clone());
+        let router =
+            GuardRouter::new("my:test", Arc::new(guard)).action("action1", "/test", get(handler));
+        let client = TestClient::new(router.build());
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(shared.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    async fn handler() {}
+}
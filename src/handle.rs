@@ -0,0 +1,226 @@
+use crate::{
+    guard::DenyKind,
+    layer::GuardOptions,
+    service::{deny, deny_event, fail, path_params},
+    Decision, Enforcement, GuardContext, OnGuard, Policy, RequestId,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, StatusCode},
+    response::Response,
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// The guard of a layer, added to the request extensions for [`GuardHandle`]
+pub(crate) struct GuardSeed<G> {
+    pub(crate) guard: Arc<G>,
+    pub(crate) options: Arc<GuardOptions>,
+    /// The layer passed the request without the guard
+    pub(crate) bypassed: bool,
+}
+
+impl<G> Clone for GuardSeed<G> {
+    fn clone(&self) -> Self {
+        Self {
+            guard: self.guard.clone(),
+            options: self.options.clone(),
+            bypassed: self.bypassed,
+        }
+    }
+}
+
+/// A handle to check more resources and actions inside a handler, like the
+/// rows of a page the caller may not all read
+///
+/// The handler takes it as an argument with the guard type of its router.
+/// A check runs `OnGuard::on_guard_decision` with a context of the request,
+/// and a denied check returns the same response the guard layer builds, so
+/// the handler returns it with `?`.
+///
+/// The checks follow the layer of the handler: they pass when the layer
+/// passed the request without the guard, with `Enforcement::Off`, `skip_if`
+/// or `skip_preflight`. The enforcement mode of the checked resource applies
+/// too, so a shadow mode deny is only logged.
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::GuardHandle;
+///
+///  async fn handler1(guard: GuardHandle<MyGuard>, Path(id): Path<String>) -> Result<String, Response> {
+///      guard.check("my:router:document", &format!("my:read:{id}")).await?;
+///      Ok(id)
+///  }
+/// ```
+pub struct GuardHandle<G> {
+    seed: GuardSeed<G>,
+    parts: Parts,
+    params: BTreeMap<String, String>,
+}
+
+impl<G> GuardHandle<G>
+where
+    G: OnGuard,
+{
+    /// Check the resource and action for the request, returning the deny
+    /// response when it is not allowed
+    pub async fn check(&self, resource: &str, action: &str) -> Result<(), Response> {
+        let options = &*self.seed.options;
+        let mode = options.mode(resource);
+        if self.seed.bypassed || mode == Enforcement::Off {
+            return Ok(());
+        }
+        let policy = Policy::new(resource, action);
+        let ctx = GuardContext::new(&policy, &self.parts, self.params.clone(), None);
+        let guard = &*self.seed.guard;
+        let decision = match guard.on_guard_decision(&ctx).await {
+            Decision::Error(error) => fail(guard, &policy, options.fail_mode, error).await,
+            decision => decision,
+        };
+        let Err(response) = decision.into_result() else {
+            return Ok(());
+        };
+        let kind = DenyKind::Resource;
+        deny_event(&policy, kind.as_str(), &response, mode);
+        if mode == Enforcement::Shadow {
+            return Ok(());
+        }
+        let request_id = self.parts.extensions.get::<RequestId>();
+        Err(deny(
+            guard,
+            options,
+            &policy,
+            Some(kind),
+            request_id,
+            response,
+        ))
+    }
+}
+
+#[async_trait]
+impl<G, S> FromRequestParts<S> for GuardHandle<G>
+where
+    G: Send + Sync + 'static,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let seed = parts.extensions.get::<GuardSeed<G>>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the handler is not guarded by a guard router of the guard",
+        ))?;
+
+        // the handle keeps a copy of the request head for the checks
+        let (mut head, _) = Request::new(()).into_parts();
+        head.method = parts.method.clone();
+        head.uri = parts.uri.clone();
+        head.version = parts.version;
+        head.headers = parts.headers.clone();
+        head.extensions = parts.extensions.clone();
+        let params = path_params(parts).await;
+        Ok(Self {
+            seed,
+            parts: head,
+            params,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuardHandle;
+    use crate::test_helper::TestClient;
+    use crate::{DenyReason, Enforcement, GuardConfig, GuardContext, GuardRouter, OnGuard};
+    use axum::{
+        extract::Path,
+        http::HeaderValue,
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct RowGuard;
+
+    impl OnGuard for RowGuard {
+        async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+            let owner = ctx
+                .headers()
+                .get("x-user")
+                .and_then(|user| user.to_str().ok());
+            match ctx.action().strip_prefix("read:") {
+                Some(row) if Some(row) != owner => Err(StatusCode::FORBIDDEN.into_response()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    async fn handler(
+        guard: GuardHandle<RowGuard>,
+        Path(row): Path<String>,
+    ) -> Result<String, Response> {
+        guard.check("my:test:row", &format!("read:{row}")).await?;
+        Ok(row)
+    }
+
+    #[tokio::test]
+    async fn test_guard_handle_check() {
+        let router = GuardRouter::new("my:test", Arc::new(RowGuard))
+            .on_deny(|reason: DenyReason| {
+                let mut response = reason.response;
+                let action = HeaderValue::from_str(&reason.action).unwrap();
+                response.headers_mut().insert("x-denied", action);
+                response
+            })
+            .action("action1", "/rows/:row", get(handler))
+            .build();
+        let client = TestClient::new(router);
+
+        let response = client.get("/rows/alice").header("x-user", "alice").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "alice");
+
+        // the same response as a deny of the guard layer
+        let response = client.get("/rows/bob").header("x-user", "alice").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["x-denied"], "read:bob");
+    }
+
+    #[tokio::test]
+    async fn test_guard_handle_follows_layer() {
+        let config = Arc::new(GuardConfig::new());
+        let router = GuardRouter::new("my:test", Arc::new(RowGuard))
+            .config(config.clone())
+            .skip_if(|parts| parts.headers.contains_key("x-internal"))
+            .action("action1", "/rows/:row", get(handler))
+            .build();
+        let client = TestClient::new(router);
+
+        // the checks pass with the request passed without the guard
+        let response = client.get("/rows/bob").header("x-internal", "1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        config.set("my:test:row", Enforcement::Shadow);
+        let status = client.get("/rows/bob").await.status();
+        assert_eq!(status, StatusCode::OK);
+
+        config.set("my:test:row", Enforcement::Off);
+        let status = client.get("/rows/bob").await.status();
+        assert_eq!(status, StatusCode::OK);
+
+        config.set("my:test:row", Enforcement::Enforce);
+        let status = client.get("/rows/bob").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_handle_not_guarded() {
+        let router = axum::Router::new().route("/rows/:row", get(handler));
+        let client = TestClient::new(router);
+        let status = client.get("/rows/alice").await.status();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -1,32 +1,225 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use super::service::GuardService;
-use crate::guard::OnGuard;
+use super::service::{GuardService, SkipIf};
+use crate::{
+    cache::ResponseCache,
+    config::GuardConfig,
+    guard::{DenyReason, FailMode, OnDeny, OnGuard},
+    limit::ConcurrencyLimit,
+    policy::{Policy, Requirement, RolesHandle},
+};
+use axum::{
+    http::{request::Parts, HeaderName},
+    response::Response,
+};
 use tower::Layer;
 
+/// Create a guard layer for a resource and action
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum::{routing::get, Router};
+///  use axum_guard_router::guard_layer;
+///
+///  async fn handler() {}
+///
+///  let app = Router::new()
+///     .route("/x", get(handler).layer(guard_layer(Arc::new(MyGuard), "my:resource", "my:get")));
+/// ```
+pub fn guard_layer<G>(
+    guard: Arc<G>,
+    resource: impl AsRef<str>,
+    action: impl AsRef<str>,
+) -> GuardActionLayer<G>
+where
+    G: OnGuard,
+{
+    GuardActionLayer::new(guard, resource, action)
+}
+
+/// A tower layer to guard a service with a resource and action
+///
+/// It is used by `GuardRouter::build` for every action, and can be applied
+/// to any axum route or method router without a guard router.
 #[derive(Clone, Debug)]
 pub struct GuardActionLayer<G> {
     pub guard: Arc<G>,
-    pub resource: String,
-    pub action: String,
-    pub roles: Option<Vec<String>>,
+    pub policy: Policy,
+    pub roles_handle: Option<RolesHandle>,
+    pub(crate) options: Arc<GuardOptions>,
+}
+
+/// The options of a guard layer, shared by all its services
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GuardOptions {
+    pub(crate) config: Option<Arc<GuardConfig>>,
+    pub(crate) server_timing: bool,
+    pub(crate) request_id: Option<HeaderName>,
+    pub(crate) csp: Option<String>,
+    pub(crate) candidates: Vec<String>,
+    pub(crate) skip_preflight: bool,
+    pub(crate) cached: bool,
+    pub(crate) roles_only: bool,
+    pub(crate) debug_headers: bool,
+    pub(crate) fail_mode: FailMode,
+    pub(crate) body_limit: Option<usize>,
+    pub(crate) response_limit: Option<usize>,
+    pub(crate) guard_timeout: Option<Duration>,
+    pub(crate) on_deny: Option<OnDeny>,
+    pub(crate) skip_if: Option<SkipIf>,
+    pub(crate) cache: Option<Arc<ResponseCache>>,
+    pub(crate) limit: Option<Arc<ConcurrencyLimit>>,
 }
 
 impl<G> GuardActionLayer<G>
 where
     G: OnGuard,
 {
-    pub fn new(guard: Arc<G>, resource: &str, action: &str) -> Self {
+    /// Create a layer checking the resource and action with the guard
+    pub fn new(guard: Arc<G>, resource: impl AsRef<str>, action: impl AsRef<str>) -> Self {
         Self {
             guard,
-            resource: resource.to_string(),
-            action: action.to_string(),
-            roles: None,
+            policy: Policy::new(resource, action),
+            roles_handle: None,
+            options: Arc::default(),
         }
     }
 
+    fn options(&mut self) -> &mut GuardOptions {
+        Arc::make_mut(&mut self.options)
+    }
+
+    /// Set the required roles
     pub fn roles(mut self, roles: &Option<Vec<String>>) -> Self {
-        self.roles.clone_from(roles);
+        self.policy.roles.clone_from(roles);
+        self
+    }
+
+    pub fn roles_handle(mut self, roles: &RolesHandle) -> Self {
+        self.roles_handle = Some(roles.clone());
+        self
+    }
+
+    pub fn requirement(mut self, requirement: Requirement) -> Self {
+        self.policy.requirement = requirement;
+        self
+    }
+
+    pub fn config(mut self, config: &Option<Arc<GuardConfig>>) -> Self {
+        self.options().config.clone_from(config);
+        self
+    }
+
+    pub fn server_timing(mut self, server_timing: bool) -> Self {
+        self.options().server_timing = server_timing;
+        self
+    }
+
+    pub fn request_id(mut self, header: &Option<HeaderName>) -> Self {
+        self.options().request_id.clone_from(header);
+        self
+    }
+
+    pub fn csp(mut self, csp: &Option<String>) -> Self {
+        self.options().csp.clone_from(csp);
+        self
+    }
+
+    /// Let the guard pick the authorized action among the candidates with
+    /// `OnGuard::on_guard_candidates`
+    pub fn candidates(mut self, candidates: &[String]) -> Self {
+        self.options().candidates = candidates.to_vec();
+        self
+    }
+
+    /// Pass the CORS preflight requests to the inner service without the guard
+    pub fn skip_preflight(mut self, skip_preflight: bool) -> Self {
+        self.options().skip_preflight = skip_preflight;
+        self
+    }
+
+    /// Reuse the roles and the allowed resource and action checked by another
+    /// cached layer of the same request, like with nested guard routers
+    pub fn cached(mut self, cached: bool) -> Self {
+        self.options().cached = cached;
+        self
+    }
+
+    /// Only check the roles, their success allows the request without the
+    /// resource and action check
+    pub fn roles_only(mut self, roles_only: bool) -> Self {
+        self.options().roles_only = roles_only;
+        self
+    }
+
+    /// Add the `X-Guard-Resource`, `X-Guard-Action` and `X-Guard-Reason`
+    /// headers to the deny responses
+    pub fn debug_headers(mut self, debug_headers: bool) -> Self {
+        self.options().debug_headers = debug_headers;
+        self
+    }
+
+    /// Set how a [`Decision::Error`](crate::Decision::Error) of the guard is handled
+    pub fn fail_mode(mut self, fail_mode: FailMode) -> Self {
+        self.options().fail_mode = fail_mode;
+        self
+    }
+
+    /// Buffer the request body up to the limit for `GuardContext::body`
+    pub fn body_limit(mut self, body_limit: Option<usize>) -> Self {
+        self.options().body_limit = body_limit;
+        self
+    }
+
+    /// Buffer the response body up to the limit for `OnGuard::on_response_body`
+    pub fn response_limit(mut self, response_limit: Option<usize>) -> Self {
+        self.options().response_limit = response_limit;
+        self
+    }
+
+    /// Set the timeout of the guard checks, handled like a guard error
+    #[cfg(feature = "timeout")]
+    pub fn guard_timeout(mut self, guard_timeout: Option<Duration>) -> Self {
+        self.options().guard_timeout = guard_timeout;
+        self
+    }
+
+    /// Build the deny responses from the guard denials
+    pub fn on_deny<F>(mut self, on_deny: F) -> Self
+    where
+        F: Fn(DenyReason) -> Response + Send + Sync + 'static,
+    {
+        self.options().on_deny = Some(OnDeny(Arc::new(on_deny)));
+        self
+    }
+
+    pub(crate) fn deny_handler(mut self, on_deny: &Option<OnDeny>) -> Self {
+        self.options().on_deny.clone_from(on_deny);
+        self
+    }
+
+    /// Pass the requests matching the predicate without the guard
+    pub fn skip_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        self.options().skip_if = Some(SkipIf(Arc::new(predicate)));
+        self
+    }
+
+    pub(crate) fn skip_predicate(mut self, skip_if: &Option<SkipIf>) -> Self {
+        self.options().skip_if.clone_from(skip_if);
+        self
+    }
+
+    pub(crate) fn cache(mut self, cache: &Option<Arc<ResponseCache>>) -> Self {
+        self.options().cache.clone_from(cache);
+        self
+    }
+
+    pub(crate) fn limit(mut self, limit: &Option<Arc<ConcurrencyLimit>>) -> Self {
+        self.options().limit.clone_from(limit);
         self
     }
 }
@@ -41,9 +234,39 @@ where
         GuardService {
             guard: self.guard.clone(),
             inner,
-            resource: self.resource.clone(),
-            action: self.action.clone(),
-            roles: self.roles.clone(),
+            policy: self.policy.clone(),
+            roles_handle: self.roles_handle.clone(),
+            options: self.options.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::guard_layer;
+    use crate::test_helper::{TestClient, TestGuard};
+    use axum::{routing::get, Router};
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_guard_layer_on_route() {
+        let guard = TestGuard::new_with(false, true);
+        let app = Router::new()
+            .route(
+                "/test",
+                get(handler).layer(
+                    guard_layer(Arc::new(guard.clone()), "my:test", "action1")
+                        .roles(&Some(vec!["admin".to_string()])),
+                ),
+            )
+            .route("/open", get(handler));
+
+        let client = TestClient::new(app);
+        assert_eq!(client.get("/test").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(client.get("/open").await.status(), StatusCode::OK);
+        assert_eq!(guard.calls(), vec!["roles:admin", "guard:my:test:action1"]);
+    }
+
+    async fn handler() {}
+}
@@ -2,8 +2,17 @@
 
 #![doc = include_str!("../README.md")]
 
-mod guard;
+mod cache;
+mod config;
+mod context;
+mod error;
+mod handle;
 mod layer;
+mod limit;
+#[macro_use]
+mod macros;
+mod policy;
+mod request_id;
 mod router;
 mod service;
 
@@ -11,5 +20,33 @@ mod service;
 mod test_helper;
 
 pub mod action;
-pub use guard::OnGuard;
-pub use router::GuardRouter;
+#[cfg(feature = "api-key")]
+pub mod api_key;
+#[cfg(feature = "audit-file")]
+pub mod audit;
+#[cfg(feature = "entitlement")]
+pub mod entitlement;
+pub mod guard;
+pub mod origin;
+#[cfg(feature = "presigned")]
+pub mod presigned;
+#[cfg(feature = "throttle")]
+pub mod throttle;
+pub mod timestamp;
+pub use config::{Enforcement, GuardConfig};
+pub use context::{GuardContext, GuardInfo, QueryLimits};
+pub use error::GuardError;
+pub use guard::{
+    guard_fn, Decision, DenyKind, DenyReason, FailMode, GuardSkipped, OnGuard, Outcome,
+};
+pub use handle::GuardHandle;
+pub use layer::{guard_layer, GuardActionLayer};
+pub use policy::{Policy, Requirement, RolesHandle};
+pub use request_id::RequestId;
+pub use router::{GuardEntry, GuardRouter};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::macros::assert_unique_routes;
+    pub use axum::routing::{on, MethodFilter};
+}
@@ -0,0 +1,44 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// The maximum concurrent requests of a route
+///
+/// A permit is acquired after the guard passes, or when the request is passed
+/// without the guard, and released once the handler returns the response.
+/// The requests over the limit do not wait.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    current: AtomicUsize,
+}
+
+/// A permit of [`ConcurrencyLimit`], released on drop
+#[derive(Debug)]
+pub(crate) struct Permit(Arc<ConcurrencyLimit>);
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquire a permit, `None` when the limit is reached
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Option<Permit> {
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < self.max).then_some(current + 1)
+            })
+            .ok()
+            .map(|_| Permit(self.clone()))
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
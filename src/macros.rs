@@ -0,0 +1,377 @@
+/// Add actions to a guard router, a same method and path can only be
+/// added once, which is checked at compile time
+///
+/// Each route is `METHOD "path" => action, handler;`, where the method is
+/// one of `GET`, `POST`, `PUT`, `DELETE`, `PATCH`, `HEAD`, `OPTIONS` and
+/// `TRACE`, and the path is a string literal.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use axum_guard_router::{guard_routes, GuardRouter, OnGuard};
+///
+/// #[derive(Clone)]
+/// struct MyGuard;
+///
+/// impl OnGuard for MyGuard {}
+///
+/// async fn handler1() {}
+/// async fn handler2() {}
+///
+/// let router: axum::Router = guard_routes!(GuardRouter::new("my:router:user", Arc::new(MyGuard)), {
+///     GET "/user" => "my:get", handler1;
+///     POST "/user" => "my:create", handler2;
+/// })
+/// .build();
+/// ```
+///
+/// A duplicate method and path does not compile:
+///
+/// ```rust,compile_fail
+/// use std::sync::Arc;
+/// use axum_guard_router::{guard_routes, GuardRouter, OnGuard};
+///
+/// #[derive(Clone)]
+/// struct MyGuard;
+///
+/// impl OnGuard for MyGuard {}
+///
+/// async fn handler1() {}
+/// async fn handler2() {}
+///
+/// let router: axum::Router = guard_routes!(GuardRouter::new("my:router:user", Arc::new(MyGuard)), {
+///     POST "/user" => "my:create", handler1;
+///     POST "/user" => "my:update", handler2;
+/// })
+/// .build();
+/// ```
+#[macro_export]
+macro_rules! guard_routes {
+    (
+        $router:expr, {
+            $($method:ident $path:literal => $name:expr, $handler:expr);+ $(;)?
+        }
+    ) => {{
+        const _: () = $crate::__private::assert_unique_routes(&[
+            $((stringify!($method), $path)),+
+        ]);
+        $router
+            $(.action(
+                $name,
+                $path,
+                $crate::__private::on($crate::__private::MethodFilter::$method, $handler),
+            ))+
+    }};
+}
+
+/// Forward the hooks of a wrapping guard to the guards it wraps
+///
+/// The target is `[field, ..]` for the guards in the fields, called in order,
+/// `(field)` for the guards of a `Vec` field, `[*field]` for the guard behind
+/// a pointer field and `*` for the guard `self` points to. The hooks are
+/// called with `$tr`, the trait of the target guards.
+///
+/// The checks and the observers are called on every guard in order, the
+/// first error is returned and the response body and the deny reason are
+/// passed from one guard to the next. The other hooks return the result of
+/// a single guard. `checks` and `observers` stand for the hooks of each group.
+macro_rules! forward_hooks {
+    ($tr:ident, $target:tt; $($hook:ident),+ $(,)?) => {
+        $(forward_hooks!(@hook $tr $target $hook);)+
+    };
+
+    (@hook $tr:ident $target:tt checks) => {
+        forward_hooks!($tr, $target; on_authenticate, on_roles, on_roles_for, on_roles_for_request);
+    };
+    (@hook $tr:ident $target:tt observers) => {
+        forward_hooks!(
+            $tr,
+            $target;
+            on_error,
+            on_allowed,
+            on_request_mut,
+            on_response,
+            on_response_body,
+            on_audit,
+            on_denied,
+            on_complete,
+            validate,
+        );
+    };
+
+    (@hook $tr:ident $target:tt on_guard) => {
+        async fn on_guard(
+            &self,
+            resource: &str,
+            action: &str,
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard {
+                $tr::on_guard(guard, resource, action).await
+            })
+        }
+    };
+    (@hook $tr:ident $target:tt on_authenticate) => {
+        async fn on_authenticate(
+            &self,
+            ctx: &$crate::GuardContext<'_>,
+        ) -> ::std::result::Result<(), String> {
+            forward_hooks!(@each self $target guard { $tr::on_authenticate(guard, ctx).await?; });
+            Ok(())
+        }
+    };
+    (@hook $tr:ident $target:tt on_roles) => {
+        async fn on_roles(
+            &self,
+            roles: &[String],
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard { $tr::on_roles(guard, roles).await?; });
+            Ok(())
+        }
+    };
+    (@hook $tr:ident $target:tt on_roles_for) => {
+        async fn on_roles_for(
+            &self,
+            resource: &str,
+            action: &str,
+            roles: &[String],
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard {
+                $tr::on_roles_for(guard, resource, action, roles).await?;
+            });
+            Ok(())
+        }
+    };
+    (@hook $tr:ident $target:tt on_roles_for_request) => {
+        async fn on_roles_for_request(
+            &self,
+            ctx: &$crate::GuardContext<'_>,
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard {
+                $tr::on_roles_for_request(guard, ctx).await?;
+            });
+            Ok(())
+        }
+    };
+    (@hook $tr:ident $target:tt on_guard_params) => {
+        async fn on_guard_params(
+            &self,
+            resource: &str,
+            action: &str,
+            params: &::std::collections::BTreeMap<String, String>,
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard {
+                $tr::on_guard_params(guard, resource, action, params).await
+            })
+        }
+    };
+    (@hook $tr:ident $target:tt on_request) => {
+        async fn on_request(
+            &self,
+            ctx: &$crate::GuardContext<'_>,
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard { $tr::on_request(guard, ctx).await })
+        }
+    };
+    (@hook $tr:ident $target:tt on_guard_decision) => {
+        async fn on_guard_decision(&self, ctx: &$crate::GuardContext<'_>) -> $crate::Decision {
+            forward_hooks!(@each self $target guard { $tr::on_guard_decision(guard, ctx).await })
+        }
+    };
+    (@hook $tr:ident $target:tt on_guard_candidates) => {
+        async fn on_guard_candidates(
+            &self,
+            ctx: &$crate::GuardContext<'_>,
+            candidates: &[String],
+        ) -> ::std::result::Result<usize, ::axum::response::Response> {
+            forward_hooks!(@each self $target guard {
+                $tr::on_guard_candidates(guard, ctx, candidates).await
+            })
+        }
+    };
+    (@hook $tr:ident $target:tt on_error) => {
+        async fn on_error(&self, resource: &str, action: &str, error: &str) {
+            forward_hooks!(@each self $target guard {
+                $tr::on_error(guard, resource, action, error).await;
+            });
+        }
+    };
+    (@hook $tr:ident $target:tt on_allowed) => {
+        async fn on_allowed(
+            &self,
+            resource: &str,
+            action: &str,
+            roles: &[String],
+            request_id: Option<&str>,
+        ) {
+            forward_hooks!(@each self $target guard {
+                $tr::on_allowed(guard, resource, action, roles, request_id).await;
+            });
+        }
+    };
+    (@hook $tr:ident $target:tt on_request_mut) => {
+        async fn on_request_mut(
+            &self,
+            request: &mut ::axum::extract::Request,
+        ) -> ::std::result::Result<(), ::axum::response::Response> {
+            forward_hooks!(@each self $target guard {
+                $tr::on_request_mut(guard, request).await?;
+            });
+            Ok(())
+        }
+    };
+    (@hook $tr:ident $target:tt on_response) => {
+        async fn on_response(
+            &self,
+            resource: &str,
+            action: &str,
+            response: &::axum::http::response::Parts,
+            request_id: Option<&str>,
+        ) {
+            forward_hooks!(@each self $target guard {
+                $tr::on_response(guard, resource, action, response, request_id).await;
+            });
+        }
+    };
+    (@hook $tr:ident $target:tt on_response_body) => {
+        async fn on_response_body(
+            &self,
+            resource: &str,
+            action: &str,
+            request: &::axum::http::Extensions,
+            response: &::axum::http::response::Parts,
+            mut body: ::axum::body::Bytes,
+        ) -> ::axum::body::Bytes {
+            forward_hooks!(@each self $target guard {
+                body = $tr::on_response_body(guard, resource, action, request, response, body)
+                    .await;
+            });
+            body
+        }
+    };
+    (@hook $tr:ident $target:tt on_audit) => {
+        async fn on_audit(
+            &self,
+            resource: &str,
+            action: &str,
+            status: ::axum::http::StatusCode,
+            elapsed: ::std::time::Duration,
+            request_id: Option<&str>,
+        ) {
+            forward_hooks!(@each self $target guard {
+                $tr::on_audit(guard, resource, action, status, elapsed, request_id).await;
+            });
+        }
+    };
+    (@hook $tr:ident $target:tt on_denied) => {
+        fn on_denied(&self, mut reason: $crate::DenyReason) -> ::axum::response::Response {
+            forward_hooks!(@each self $target guard {
+                reason = reason.pass(|reason| $tr::on_denied(guard, reason));
+            });
+            reason.response
+        }
+    };
+    (@hook $tr:ident $target:tt on_complete) => {
+        fn on_complete(&self, resource: &str, action: &str, outcome: $crate::Outcome) {
+            forward_hooks!(@each self $target guard {
+                $tr::on_complete(guard, resource, action, outcome);
+            });
+        }
+    };
+    (@hook $tr:ident $target:tt validate) => {
+        fn validate(&self, manifest: &[$crate::GuardEntry]) -> ::std::result::Result<(), String> {
+            forward_hooks!(@each self $target guard { $tr::validate(guard, manifest)?; });
+            Ok(())
+        }
+    };
+
+    // bind each target guard to `$guard` and run the block with it
+    (@each $this:ident * $guard:ident $block:block) => {{
+        let $guard = &**$this;
+        $block
+    }};
+    (@each $this:ident [*$field:ident] $guard:ident $block:block) => {{
+        let $guard = &*$this.$field;
+        $block
+    }};
+    (@each $this:ident ($field:ident) $guard:ident $block:block) => {
+        for $guard in &$this.$field $block
+    };
+    (@each $this:ident [$($field:ident),+] $guard:ident $block:block) => {
+        $({
+            let $guard = &$this.$field;
+            $block
+        })+
+    };
+}
+
+/// Panic at compile time when a method and path repeats
+#[doc(hidden)]
+pub const fn assert_unique_routes(routes: &[(&str, &str)]) {
+    let mut i = 0;
+    while i < routes.len() {
+        let mut j = i + 1;
+        while j < routes.len() {
+            if str_eq(routes[i].0, routes[j].0) && str_eq(routes[i].1, routes[j].1) {
+                panic!("guard_routes! has a duplicate method and path");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helper::{TestClient, TestGuard};
+    use crate::GuardRouter;
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_guard_routes() {
+        let guard = TestGuard::new_with(true, true);
+        let router = guard_routes!(GuardRouter::new("my:test", Arc::new(guard.clone())), {
+            GET "/test" => "action1", handler;
+            POST "/test" => "action2", handler;
+            GET "/test2" => "action3", handler;
+        })
+        .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test2").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:action1",
+                "guard:my:test:action2",
+                "guard:my:test:action3"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate method and path")]
+    fn test_assert_unique_routes() {
+        super::assert_unique_routes(&[("GET", "/test"), ("POST", "/test"), ("GET", "/test")]);
+    }
+
+    async fn handler() {}
+}
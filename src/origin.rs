@@ -0,0 +1,159 @@
+//! A guard to check the origin of the state-changing browser requests
+//!
+//! ```rust,ignore
+//!  use std::sync::Arc;
+//!  use axum_guard_router::{guard, origin::OriginGuard};
+//!
+//!  let guard = guard::chain(OriginGuard::new(&["https://app.example.com"]), MyGuard);
+//!  let router = GuardRouter::new("my:router:resource", Arc::new(guard))
+//!     .action("my:create", "/user", post(handler));
+//! ```
+use crate::{GuardContext, OnGuard};
+use axum::{
+    http::{header, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+
+/// A guard to deny the cross-origin state-changing requests, against CSRF
+///
+/// The safe methods (`GET`, `HEAD`, `OPTIONS` and `TRACE`) always pass. For
+/// the other methods, the origin is read from the `Origin` header, or the
+/// `Referer` header when it is missing, and must be the same origin as the
+/// `Host` header or in the allowed origins, otherwise it is denied with 403.
+/// The requests without both headers are not sent by a browser and pass.
+#[derive(Clone, Debug, Default)]
+pub struct OriginGuard {
+    pub allowed_origins: Vec<String>,
+}
+
+impl OriginGuard {
+    /// Create a guard with the allowed origins, like `https://app.example.com`
+    pub fn new(allowed_origins: &[&str]) -> Self {
+        Self {
+            allowed_origins: allowed_origins
+                .iter()
+                .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+                .collect(),
+        }
+    }
+}
+
+impl OnGuard for OriginGuard {
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        if is_safe(ctx.method()) {
+            return Ok(());
+        }
+
+        let headers = ctx.headers();
+        let Some(value) = headers
+            .get(header::ORIGIN)
+            .or_else(|| headers.get(header::REFERER))
+        else {
+            return Ok(());
+        };
+
+        let origin = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<Uri>().ok())
+            .and_then(|uri| Some((uri.scheme_str()?.to_string(), uri.authority()?.clone())));
+        if let Some((scheme, authority)) = origin {
+            let host = headers
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok());
+            if host.is_some_and(|host| host.eq_ignore_ascii_case(authority.as_str())) {
+                return Ok(());
+            }
+
+            let origin = format!("{scheme}://{authority}").to_ascii_lowercase();
+            if self.allowed_origins.contains(&origin) {
+                return Ok(());
+            }
+        }
+
+        log::debug!("OriginGuard: origin={value:?} is not allowed");
+        Err((StatusCode::FORBIDDEN, "cross-origin request").into_response())
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OriginGuard;
+    use crate::test_helper::TestClient;
+    use crate::{action, GuardRouter};
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    fn client() -> TestClient {
+        let guard = OriginGuard::new(&["https://admin.example.com/"]);
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .route(
+                "/test",
+                action::get("action1", handler).post("action2", handler),
+            )
+            .build();
+        TestClient::new(router)
+    }
+
+    #[tokio::test]
+    async fn test_origin_cross_origin_post_403() {
+        let client = client();
+        let status = client
+            .post("/test")
+            .header("host", "app.example.com")
+            .header("origin", "https://evil.example.com")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = client
+            .post("/test")
+            .header("host", "app.example.com")
+            .header("referer", "https://evil.example.com/page")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = client.post("/test").header("origin", "null").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_origin_same_origin_post() {
+        let client = client();
+        let status = client
+            .post("/test")
+            .header("host", "app.example.com")
+            .header("origin", "https://app.example.com")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+
+        let status = client
+            .post("/test")
+            .header("host", "app.example.com")
+            .header("origin", "https://Admin.example.com")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_origin_get_pass() {
+        let status = client()
+            .get("/test")
+            .header("origin", "https://evil.example.com")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    async fn handler() {}
+}
@@ -0,0 +1,86 @@
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+/// How the roles of a policy are matched
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Requirement {
+    /// Pass the whole role list to `OnGuard::on_roles` at once,
+    /// the guard decides whether the caller holds one of them
+    #[default]
+    Any,
+    /// Check every role with its own `OnGuard::on_roles` call,
+    /// all of them must pass
+    All,
+}
+
+/// A policy combines the required roles with the resource and action check
+///
+/// # Example
+///
+/// ```rust,ignore
+///  use axum_guard_router::{Policy, Requirement};
+///
+///  let policy = Policy::new("my:router:admin", "my:manage")
+///     .roles(&["admin".to_string(), "owner".to_string()])
+///     .requirement(Requirement::All);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub resource: String,
+    pub action: String,
+    pub roles: Option<Vec<String>>,
+    pub requirement: Requirement,
+}
+
+impl Policy {
+    /// Create a policy with resource and action
+    pub fn new(resource: impl AsRef<str>, action: impl AsRef<str>) -> Self {
+        Self {
+            resource: resource.as_ref().to_string(),
+            action: action.as_ref().to_string(),
+            roles: None,
+            requirement: Requirement::default(),
+        }
+    }
+
+    /// Set the required roles of the policy
+    pub fn roles(mut self, roles: &[String]) -> Self {
+        self.roles = Some(roles.to_vec());
+        self
+    }
+
+    /// Set how the roles are matched
+    pub fn requirement(mut self, requirement: Requirement) -> Self {
+        self.requirement = requirement;
+        self
+    }
+}
+
+/// A shared handle to read and replace the required roles at runtime
+///
+/// The guard service loads the current roles on every request, so the
+/// change applies to the built router without rebuilding it.
+#[derive(Clone, Debug, Default)]
+pub struct RolesHandle(Arc<ArcSwap<Option<Vec<String>>>>);
+
+impl RolesHandle {
+    /// Create a handle with the initial roles
+    pub fn new(roles: Option<Vec<String>>) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(roles)))
+    }
+
+    /// Get the current roles
+    pub fn get(&self) -> Option<Vec<String>> {
+        self.0.load().as_ref().clone()
+    }
+
+    /// Replace the required roles
+    pub fn set(&self, roles: &[String]) {
+        self.0.store(Arc::new(Some(roles.to_vec())));
+    }
+
+    /// Remove the required roles, the roles check is skipped
+    pub fn clear(&self) {
+        self.0.store(Arc::new(None));
+    }
+}
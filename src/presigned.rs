@@ -0,0 +1,219 @@
+//! A guard to validate presigned urls
+//!
+//! ```rust,ignore
+//!  use std::{sync::Arc, time::{Duration, SystemTime}};
+//!  use axum_guard_router::presigned::PresignedGuard;
+//!
+//!  let guard = PresignedGuard::new(b"my secret");
+//!  // share /files/report.pdf?expires=...&signature=...
+//!  let url = guard.sign("/files/report.pdf", SystemTime::now() + Duration::from_secs(600));
+//!
+//!  let router = GuardRouter::new("my:router:files", Arc::new(guard))
+//!     .action("my:download", "/files/:name", get(handler));
+//! ```
+use crate::{
+    timestamp::{Clock, SystemClock},
+    GuardContext, OnGuard,
+};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The query parameter with the expiry in unix seconds
+pub const EXPIRES_PARAM: &str = "expires";
+
+/// The query parameter with the hex encoded signature
+pub const SIGNATURE_PARAM: &str = "signature";
+
+/// A guard to check the signature and expiry of presigned urls
+///
+/// The signature is the hex encoded HMAC-SHA256 of the path and the query
+/// without the `signature` parameter, in the request order. The path is the
+/// one seen by the guard router, inside a nested router the nest prefix is
+/// stripped. A missing or invalid signature is denied with 403, and a valid
+/// one after its `expires` time with 410.
+#[derive(Clone)]
+pub struct PresignedGuard<C = SystemClock> {
+    secret: Vec<u8>,
+    clock: C,
+}
+
+impl PresignedGuard {
+    /// Create a guard with the signing secret and the system clock
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            clock: SystemClock,
+        }
+    }
+}
+
+impl<C> PresignedGuard<C> {
+    /// Replace the clock used to check the expiry
+    pub fn clock<T: Clock>(self, clock: T) -> PresignedGuard<T> {
+        PresignedGuard {
+            secret: self.secret,
+            clock,
+        }
+    }
+
+    /// Sign the path with an optional query, and return it with the
+    /// `expires` and `signature` parameters appended
+    pub fn sign(&self, path: &str, expires: SystemTime) -> String {
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let unsigned = format!("{path}{separator}{EXPIRES_PARAM}={expires}");
+
+        let signature = self.mac(&unsigned).finalize().into_bytes().iter().fold(
+            String::new(),
+            |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            },
+        );
+        format!("{unsigned}&{SIGNATURE_PARAM}={signature}")
+    }
+
+    fn mac(&self, data: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("hmac accepts any key length");
+        mac.update(data.as_bytes());
+        mac
+    }
+}
+
+impl<C> OnGuard for PresignedGuard<C>
+where
+    C: Clock,
+{
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        let forbidden = || (StatusCode::FORBIDDEN, "invalid signature").into_response();
+
+        let uri = ctx.uri();
+        let query = uri.query().unwrap_or_default();
+        let mut signature = None;
+        let mut expires = None;
+        let mut unsigned = Vec::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            match pair.split_once('=') {
+                Some((SIGNATURE_PARAM, value)) => signature = Some(value),
+                Some((EXPIRES_PARAM, value)) => {
+                    expires = value.parse::<u64>().ok();
+                    unsigned.push(pair);
+                }
+                _ => unsigned.push(pair),
+            }
+        }
+
+        let signature = signature.and_then(decode_hex).ok_or_else(forbidden)?;
+        let data = format!("{}?{}", uri.path(), unsigned.join("&"));
+        self.mac(&data)
+            .verify_slice(&signature)
+            .map_err(|_| forbidden())?;
+
+        let expires = expires
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+            .ok_or_else(forbidden)?;
+        if self.clock.now() > expires {
+            return Err((StatusCode::GONE, "url expired").into_response());
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PresignedGuard;
+    use crate::test_helper::TestClient;
+    use crate::timestamp::Clock;
+    use crate::GuardRouter;
+    use axum::routing::get;
+    use reqwest::StatusCode;
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[derive(Clone)]
+    struct FixedClock;
+
+    impl Clock for FixedClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(NOW)
+        }
+    }
+
+    fn guard() -> PresignedGuard<FixedClock> {
+        PresignedGuard::new(b"secret").clock(FixedClock)
+    }
+
+    fn client() -> TestClient {
+        let router = GuardRouter::new("my:test", Arc::new(guard()))
+            .action("action1", "/files/:name", get(handler))
+            .build();
+        TestClient::new(router)
+    }
+
+    #[tokio::test]
+    async fn test_presigned_valid() {
+        let url = guard().sign(
+            "/files/a.txt?size=10",
+            FixedClock.now() + Duration::from_secs(60),
+        );
+        let status = client().get(&url).await.status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_presigned_tampered_403() {
+        let client = client();
+        let url = guard().sign(
+            "/files/a.txt?size=10",
+            FixedClock.now() + Duration::from_secs(60),
+        );
+
+        let status = client.get(&url.replace("a.txt", "b.txt")).await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = client
+            .get(&url.replace("size=10", "size=99"))
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = client.get("/files/a.txt").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_presigned_expired_410() {
+        let url = guard().sign("/files/a.txt", FixedClock.now() - Duration::from_secs(1));
+        let status = client().get(&url).await.status();
+        assert_eq!(status, StatusCode::GONE);
+    }
+
+    async fn handler() {}
+}
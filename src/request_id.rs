@@ -0,0 +1,54 @@
+use axum::http::{request::Parts, HeaderName};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The request id read or generated by the guard service
+///
+/// It is stored in the request extensions, so handlers can use
+/// `Extension<RequestId>`, and guards can read it with
+/// `GuardContext::request_id`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Read the id from the request header or the extensions set by an outer
+    /// guard layer, or generate a new one
+    pub(crate) fn resolve(parts: &mut Parts, header: &HeaderName) -> Self {
+        if let Some(id) = parts.extensions.get::<RequestId>() {
+            return id.clone();
+        }
+
+        let id = parts
+            .headers
+            .get(header)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(|value| RequestId(value.to_string()))
+            .unwrap_or_else(RequestId::generate);
+        parts.extensions.insert(id.clone());
+        id
+    }
+
+    fn generate() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        RequestId(format!("{nanos:016x}-{count:08x}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
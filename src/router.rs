@@ -1,12 +1,70 @@
-use super::{action::Action, guard::OnGuard, layer::GuardActionLayer};
-use axum::{routing::MethodRouter, Router};
-use std::sync::Arc;
+use super::{
+    action::{filter_methods, validate_name, Action, ActionRoute, CrudHandlers, DecisionHandler},
+    cache::ResponseCache,
+    config::GuardConfig,
+    context::GuardState,
+    guard::{DenyReason, FailMode, OnDeny, OnGuard},
+    layer::GuardActionLayer,
+    policy::{Policy, Requirement, RolesHandle},
+    service::SkipIf,
+};
+use axum::{
+    extract::Request,
+    handler::Handler,
+    http::{request::Parts, HeaderName, Method},
+    response::{IntoResponse, Response},
+    routing::{MethodFilter, MethodRouter, Route},
+    Extension, Router,
+};
+use std::{convert::Infallible, fmt, sync::Arc, time::Duration};
+use tower::{Layer, Service};
+
+type NameFn = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+type LayerFn<S> = dyn Fn(MethodRouter<S>) -> MethodRouter<S> + Send + Sync;
+
+/// A guarded endpoint of the guard router, see `GuardRouter::manifest`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuardEntry {
+    pub resource: String,
+    pub action: String,
+    pub path: String,
+    /// The methods of the action, empty when they are unknown
+    pub methods: Vec<Method>,
+    pub roles: Option<Vec<String>>,
+}
 
 #[derive(Clone)]
 pub struct GuardRouter<G, S = ()> {
     resource: String,
-    roles: Option<Vec<String>>,
+    roles: RolesHandle,
+    requirement: Requirement,
+    policy_action: Option<String>,
+    config: Option<Arc<GuardConfig>>,
+    server_timing: bool,
+    skip_preflight: bool,
+    cached: bool,
+    roles_only: bool,
+    debug_headers: bool,
+    fail_mode: FailMode,
+    body_limit: Option<usize>,
+    response_limit: Option<usize>,
+    #[cfg(feature = "timeout")]
+    guard_timeout: Option<Duration>,
+    on_deny: Option<OnDeny>,
+    skip_if: Option<SkipIf>,
+    request_id: Option<HeaderName>,
+    cache: Option<Arc<ResponseCache>>,
     actions: Vec<(String, Action<S>)>,
+    inherit_resource: bool,
+    inherit_roles: bool,
+    nested: Vec<(String, Router<S>)>,
+    nested_entries: Vec<GuardEntry>,
+    public: Vec<(String, MethodRouter<S>)>,
+    fallback: Option<Action<S>>,
+    method_not_allowed: Option<MethodRouter<S>>,
+    name_validator: Option<Arc<NameFn>>,
+    layers: Vec<Arc<LayerFn<S>>>,
+    route_layers: Vec<Arc<LayerFn<S>>>,
     guard: Arc<G>,
 }
 
@@ -50,17 +108,46 @@ where
     ///  let router = GuardRouter::new("my:router:resource", Arc::new(MyGuard));
     ///
     /// ```
-    pub fn new(resource: &str, guard: Arc<G>) -> Self {
+    #[track_caller]
+    pub fn new(resource: impl AsRef<str>, guard: Arc<G>) -> Self {
+        validate_name("resource", resource.as_ref());
         Self {
             guard,
-            resource: resource.to_string(),
+            resource: resource.as_ref().to_string(),
             actions: Vec::new(),
-            roles: None,
+            inherit_resource: false,
+            inherit_roles: false,
+            nested: Vec::new(),
+            nested_entries: Vec::new(),
+            public: Vec::new(),
+            fallback: None,
+            method_not_allowed: None,
+            name_validator: None,
+            layers: Vec::new(),
+            route_layers: Vec::new(),
+            roles: RolesHandle::default(),
+            requirement: Requirement::default(),
+            policy_action: None,
+            config: None,
+            server_timing: false,
+            skip_preflight: false,
+            cached: false,
+            roles_only: false,
+            debug_headers: false,
+            fail_mode: FailMode::default(),
+            body_limit: None,
+            response_limit: None,
+            #[cfg(feature = "timeout")]
+            guard_timeout: None,
+            on_deny: None,
+            skip_if: None,
+            request_id: None,
+            cache: None,
         }
     }
 
     /// Create a guard router with action
-    /// one path can only create one action with axum::routing::get, post, put, delete
+    /// the actions of a same path are merged, each of them with a different method
     /// # Example
     ///
     /// ```rust,ignore
@@ -98,14 +185,238 @@ where
     ///     .action("my:update", "/user", put(handler2));
     ///
     /// ```
-    pub fn action(mut self, name: &str, path: &str, method_router: MethodRouter<S>) -> Self {
-        let action = Action::create(name, method_router);
+    #[track_caller]
+    pub fn action(
+        mut self,
+        name: impl AsRef<str>,
+        path: &str,
+        method_router: MethodRouter<S>,
+    ) -> Self {
+        let action = Action::create(name.as_ref(), None, method_router);
+        self.actions.push((path.to_string(), action));
+        self
+    }
+
+    /// Add the conventional routes of a REST resource, with the actions named
+    /// after the last segment of the path
+    ///
+    /// For `/users`, the routes are `GET /users` (`users:list`),
+    /// `POST /users` (`users:create`), `GET /users/:id` (`users:get`),
+    /// `PUT /users/:id` (`users:update`) and `DELETE /users/:id`
+    /// (`users:delete`). The handlers read the id with `Path`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last segment of the path is empty or a parameter, like
+    /// `/` or `/users/:id/`, as it names the actions.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum_guard_router::action::CrudHandlers;
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .resource(
+    ///         "/users",
+    ///         CrudHandlers::new()
+    ///             .index(list_users)
+    ///             .show(get_user)
+    ///             .create(create_user)
+    ///             .update(update_user)
+    ///             .delete(delete_user),
+    ///     );
+    /// ```
+    #[track_caller]
+    pub fn resource(mut self, path: &str, handlers: CrudHandlers<S>) -> Self {
+        let path = path.trim_end_matches('/');
+        let name = path.rsplit('/').next().unwrap_or_default();
+        if name.is_empty() || name.starts_with([':', '*']) {
+            panic!("Cannot name the CRUD actions of {path:?}, the last segment must be a name");
+        }
+        for (action, item, filter, router) in handlers.routes {
+            let path = match item {
+                true => format!("{path}/:id"),
+                false => path.to_string(),
+            };
+            let action = Action::new().on_router(filter, &format!("{name}:{action}"), router);
+            self.actions.push((path, action));
+        }
+        self
+    }
+
+    /// Add a public route to the router, which is not guarded, like a health
+    /// check
+    ///
+    /// It is merged with the guarded actions of the same path, each with a
+    /// different method. It is not in the manifest, and the layers added with
+    /// `layer` and `route_layer` do not apply to it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn health() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .action("my:get", "/user", get(handler1))
+    ///     .public_route("/user/health", get(health));
+    /// ```
+    pub fn public_route(mut self, path: &str, method_router: MethodRouter<S>) -> Self {
+        self.public.push((path.to_string(), method_router));
+        self
+    }
+
+    /// Create a guard router with an action of a tower service, like a
+    /// `ServeDir` or a reverse proxy, accepting the requests of any method
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  let router = GuardRouter::new("my:router:file", Arc::new(MyGuard))
+    ///     .action_service("my:read", "/assets", ServeDir::new("assets"));
+    /// ```
+    #[track_caller]
+    pub fn action_service<T>(mut self, name: impl AsRef<str>, path: &str, service: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+        T::Response: IntoResponse + 'static,
+        T::Future: Send + 'static,
+    {
+        let action = Action::new().any_service(name, service);
+        self.actions.push((path.to_string(), action));
+        self
+    }
+
+    /// Create a guard router with action and the action roles
+    /// the action roles override the router roles for this action
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn handler2() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .roles(&["viewer".to_string()])
+    ///     .action("my:get", "/user", get(handler1))
+    ///     .action_with_roles("my:delete", "/user", &["admin".to_string()], delete(handler2));
+    ///
+    /// ```
+    #[track_caller]
+    pub fn action_with_roles(
+        mut self,
+        name: impl AsRef<str>,
+        path: &str,
+        roles: &[String],
+        method_router: MethodRouter<S>,
+    ) -> Self {
+        let action = Action::create(name.as_ref(), Some(roles), method_router);
+        self.actions.push((path.to_string(), action));
+        self
+    }
+
+    /// Create a guard router with action satisfied by several candidate actions
+    ///
+    /// The guard picks the one it authorizes with `OnGuard::on_guard_candidates`,
+    /// and the handler reads it from the `GuardInfo` action. The first candidate
+    /// is the action name.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum_guard_router::GuardInfo;
+    ///
+    ///  async fn handler1(info: GuardInfo) -> String {
+    ///      info.action
+    ///  }
+    ///
+    ///  let router = GuardRouter::new("my:router:doc", Arc::new(MyGuard))
+    ///     .action_candidates(&["my:read:own", "my:read:all"], "/doc", get(handler1));
+    ///
+    /// ```
+    #[track_caller]
+    pub fn action_candidates(
+        mut self,
+        names: &[&str],
+        path: &str,
+        method_router: MethodRouter<S>,
+    ) -> Self {
+        let candidates: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let name = candidates.first().map_or("", |name| name.as_str());
+        let action = Action::create(name, None, method_router).candidates(&candidates);
+        self.actions.push((path.to_string(), action));
+        self
+    }
+
+    /// Create a guard router with action and the maximum concurrent requests
+    /// the requests over the limit are rejected with 503 after the guard passes,
+    /// the limit holds for the requests passed without the guard too
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:report", Arc::new(MyGuard))
+    ///     .action_max_concurrent("my:export", "/export", post(handler1), 4);
+    ///
+    /// ```
+    #[track_caller]
+    pub fn action_max_concurrent(
+        mut self,
+        name: impl AsRef<str>,
+        path: &str,
+        method_router: MethodRouter<S>,
+        max: usize,
+    ) -> Self {
+        let action = Action::create(name.as_ref(), None, method_router).max_concurrent(max);
+        self.actions.push((path.to_string(), action));
+        self
+    }
+
+    /// Create a guard router with action and a handler receiving the guard decision
+    ///
+    /// The first argument of the handler is the `GuardInfo` of the guard which
+    /// allowed the request, with the resource, action and roles it checked,
+    /// see `action::DecisionHandler`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum::{extract::Path, routing::MethodFilter};
+    ///  use axum_guard_router::GuardInfo;
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .action_with_decision(
+    ///         "my:get",
+    ///         "/user/:id",
+    ///         MethodFilter::GET,
+    ///         |decision: GuardInfo, Path(id): Path<String>| async move {
+    ///             format!("{id} is authorized for {}", decision.action)
+    ///         },
+    ///     );
+    ///
+    /// ```
+    #[track_caller]
+    pub fn action_with_decision<H, T>(
+        mut self,
+        name: impl AsRef<str>,
+        path: &str,
+        filter: MethodFilter,
+        handler: H,
+    ) -> Self
+    where
+        H: DecisionHandler<T, S>,
+    {
+        let action = handler.on(Action::new(), filter, name.as_ref());
         self.actions.push((path.to_string(), action));
         self
     }
 
     /// Create a guard router with actions
     /// a same path can create multiple actions with action::get, post, put, delete.
+    /// The action can also be built from a fallible method router with
+    /// `Action::from_method_router`, its errors are responded with 500.
     ///
     /// # Example
     ///
@@ -142,8 +453,344 @@ where
     ///     .route("/user", post("my:create", handler).put("my:update", handler2));
     ///
     /// ```
-    pub fn route(mut self, path: &str, action: Action<S>) -> Self {
-        self.actions.push((path.to_string(), action));
+    pub fn route<E>(mut self, path: &str, action: Action<S, E>) -> Self
+    where
+        E: fmt::Display + Send + 'static,
+    {
+        self.actions.push((path.to_string(), action.handle_error()));
+        self
+    }
+
+    /// Create a guard router with actions and the roles of the path
+    /// the path roles override the router roles, the actions created with
+    /// their own roles, like `action::get_with_roles`, keep them
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn handler2() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .roles(&["viewer".to_string()])
+    ///     .route_with_roles(
+    ///         "/admin",
+    ///         &["admin".to_string()],
+    ///         action::get("my:get", handler1).put("my:update", handler2),
+    ///     );
+    ///
+    /// ```
+    pub fn route_with_roles<E>(self, path: &str, roles: &[String], action: Action<S, E>) -> Self
+    where
+        E: fmt::Display + Send + 'static,
+    {
+        self.route(path, action.default_roles(roles))
+    }
+
+    /// Merge the actions of another guard router with the same resource
+    ///
+    /// The merged actions use the guard of this router. The roles of `other`
+    /// are copied into its actions without their own roles, so later changes
+    /// through its `roles_handle` are not seen, and its actions without any
+    /// roles check the roles of this router. The other options apply to the
+    /// whole router, so they are only set on this router, or set to the same
+    /// on both routers, like a shared `on_deny` of a router they are cloned
+    /// from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the two routers guard different resources, register the
+    /// same path and method, or `other` sets a router option different from
+    /// this router, like its own `config`, `on_deny` or layers. The methods of
+    /// the actions added with a method router are unknown, like in
+    /// `GuardRouter::manifest`, so their conflicts panic in `build`, like
+    /// `axum::Router::merge`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn handler2() {}
+    ///
+    ///  let read = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .action("my:get", "/user", get(handler1));
+    ///  let write = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .action("my:create", "/user", post(handler2));
+    ///
+    ///  let router = read.merge(write).build();
+    /// ```
+    #[track_caller]
+    pub fn merge(mut self, mut other: GuardRouter<G, S>) -> Self {
+        assert_eq!(
+            self.resource, other.resource,
+            "Cannot merge guard routers with different resources"
+        );
+        self.check_merge_options(&other);
+        // the router roles of other move into its actions
+        if let Some(roles) = other.roles.get() {
+            other.actions = std::mem::take(&mut other.actions)
+                .into_iter()
+                .map(|(path, action)| (path, action.default_roles(&roles)))
+                .collect();
+            other.fallback = other.fallback.map(|action| action.default_roles(&roles));
+        }
+        let has_roles = other
+            .actions
+            .iter()
+            .map(|(_, action)| action)
+            .chain(&other.fallback)
+            .flat_map(|action| action.routes())
+            .any(|entry| entry.roles.is_some());
+        assert!(
+            !has_roles || self.requirement == other.requirement,
+            "Cannot merge guard routers with a different roles requirement"
+        );
+        for (path, action) in &other.actions {
+            let methods = action
+                .routes()
+                .iter()
+                .filter_map(|entry| entry.filter)
+                .flat_map(filter_methods);
+            for method in methods {
+                let conflict = self
+                    .actions
+                    .iter()
+                    .filter(|(p, _)| p == path)
+                    .flat_map(|(_, action)| action.routes())
+                    .filter_map(|entry| entry.filter)
+                    .any(|filter| filter_methods(filter).contains(&method));
+                assert!(
+                    !conflict,
+                    "Cannot merge guard routers with the same route {method} {path}"
+                );
+            }
+        }
+        self.actions.extend(other.actions);
+        self.public.extend(other.public);
+        self.nested.extend(other.nested);
+        self.nested_entries.extend(other.nested_entries);
+        self.fallback = self.fallback.or(other.fallback);
+        self
+    }
+
+    /// Check the router options of `other` are unset or the same as this router
+    #[track_caller]
+    fn check_merge_options(&self, other: &GuardRouter<G, S>) {
+        fn same<T: ?Sized>(this: Option<&Arc<T>>, other: Option<&Arc<T>>) -> bool {
+            match (this, other) {
+                (_, None) => true,
+                (Some(this), Some(other)) => Arc::ptr_eq(this, other),
+                (None, Some(_)) => false,
+            }
+        }
+        fn same_value<T: PartialEq>(this: &Option<T>, other: &Option<T>) -> bool {
+            other.is_none() || this == other
+        }
+        let same_layers = |this: &[Arc<LayerFn<S>>], other: &[Arc<LayerFn<S>>]| {
+            other.is_empty()
+                || (this.len() == other.len()
+                    && this.iter().zip(other).all(|(a, b)| Arc::ptr_eq(a, b)))
+        };
+        let options = [
+            (
+                "policy",
+                same_value(&self.policy_action, &other.policy_action),
+            ),
+            ("config", same(self.config.as_ref(), other.config.as_ref())),
+            ("server_timing", !other.server_timing || self.server_timing),
+            (
+                "skip_preflight",
+                !other.skip_preflight || self.skip_preflight,
+            ),
+            ("cached", !other.cached || self.cached),
+            ("roles_only", !other.roles_only || self.roles_only),
+            ("debug_headers", !other.debug_headers || self.debug_headers),
+            (
+                "on_failure",
+                other.fail_mode == FailMode::default() || self.fail_mode == other.fail_mode,
+            ),
+            (
+                "buffer_body",
+                same_value(&self.body_limit, &other.body_limit),
+            ),
+            (
+                "filter_response",
+                same_value(&self.response_limit, &other.response_limit),
+            ),
+            (
+                "on_deny",
+                same(
+                    self.on_deny.as_ref().map(|on_deny| &on_deny.0),
+                    other.on_deny.as_ref().map(|on_deny| &on_deny.0),
+                ),
+            ),
+            (
+                "skip_if",
+                same(
+                    self.skip_if.as_ref().map(|skip_if| &skip_if.0),
+                    other.skip_if.as_ref().map(|skip_if| &skip_if.0),
+                ),
+            ),
+            (
+                "with_request_id",
+                same_value(&self.request_id, &other.request_id),
+            ),
+            (
+                "response_cache",
+                same(self.cache.as_ref(), other.cache.as_ref()),
+            ),
+            (
+                "method_not_allowed_fallback",
+                other.method_not_allowed.is_none(),
+            ),
+            (
+                "validate_names",
+                same(self.name_validator.as_ref(), other.name_validator.as_ref()),
+            ),
+            ("layer", same_layers(&self.layers, &other.layers)),
+            (
+                "route_layer",
+                same_layers(&self.route_layers, &other.route_layers),
+            ),
+            #[cfg(feature = "timeout")]
+            (
+                "guard_timeout",
+                same_value(&self.guard_timeout, &other.guard_timeout),
+            ),
+        ];
+        if let Some((option, _)) = options.iter().find(|(_, same)| !same) {
+            panic!("Cannot merge guard routers with a different {option} option");
+        }
+    }
+
+    /// Nest a child guard router at the path
+    ///
+    /// The child routes are guarded by the child guard only. When the child
+    /// is created with `inherit_resource`, its resource is appended to the
+    /// parent resource as is, without a delimiter, so the child resource
+    /// starts with its own delimiter, like `:profile`. The children without
+    /// it keep their full resource. A child created with `inherit_roles` and
+    /// without its own roles checks the parent roles, so they are set before
+    /// the child is nested.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let profile = GuardRouter::new(":profile", Arc::new(MyGuard))
+    ///     .inherit_resource()
+    ///     .action("my:get", "/profile", get(handler1));
+    ///
+    ///  // the guard of /user/profile checks `admin:user:profile`
+    ///  let router = GuardRouter::new("admin:user", Arc::new(MyGuard))
+    ///     .nest("/user", profile)
+    ///     .build();
+    /// ```
+    pub fn nest<C>(mut self, path: &str, mut child: GuardRouter<C, S>) -> Self
+    where
+        C: OnGuard + Clone + Send + Sync + 'static,
+    {
+        if child.inherit_resource {
+            child.resource = format!("{}{}", self.resource, child.resource);
+        }
+        if child.inherit_roles && child.roles.get().is_none() {
+            // the handle is shared, so a change of the parent roles applies too
+            child.roles = self.roles.clone();
+            child.requirement = self.requirement;
+        }
+        self.nested_entries
+            .extend(child.manifest().into_iter().map(|mut entry| {
+                entry.path = format!("{}{}", path.trim_end_matches('/'), entry.path);
+                entry
+            }));
+        self.nested.push((path.to_string(), child.into_router()));
+        self
+    }
+
+    /// Guard the requests of the paths not matched by the router with the
+    /// action, before the fallback handler responds
+    ///
+    /// Without it, the unmatched paths get a plain 404 without the guard.
+    /// The nested routers without their own fallback use it too, as in axum.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn not_found() -> StatusCode {
+    ///      StatusCode::NOT_FOUND
+    ///  }
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .action("my:get", "/admin", get(handler1))
+    ///     .fallback("my:unknown", not_found);
+    /// ```
+    #[track_caller]
+    pub fn fallback<H, T>(mut self, name: impl AsRef<str>, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.fallback = Some(Action::new().any(name, handler));
+        self
+    }
+
+    /// Guard the requests of the paths not matched by the router with the
+    /// action, before the fallback service responds, like a `ServeDir` of
+    /// the static files, see `GuardRouter::fallback`
+    #[track_caller]
+    pub fn fallback_service<T>(mut self, name: impl AsRef<str>, service: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+        T::Response: IntoResponse + 'static,
+        T::Future: Send + 'static,
+    {
+        let method_router = MethodRouter::new().fallback_service(service);
+        self.fallback = Some(Action::from_method_router(name, method_router));
+        self
+    }
+
+    /// Respond the requests of a routed path with a method it does not route,
+    /// like a JSON `405 Method Not Allowed`, instead of the empty axum one
+    ///
+    /// The handler is the method fallback of every path of the router, and
+    /// runs without the guard, like the axum one. The paths with an
+    /// `action::any` action accept every method and keep it as their
+    /// fallback. A method router with its own fallback, like `routing::any`,
+    /// can not be merged with it, so `build` panics.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn method_not_allowed() -> (StatusCode, Json<Value>) {
+    ///      (StatusCode::METHOD_NOT_ALLOWED, Json(json!({ "error": "method not allowed" })))
+    ///  }
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .method_not_allowed_fallback(method_not_allowed)
+    ///     .action("my:get", "/admin", get(handler1));
+    /// ```
+    pub fn method_not_allowed_fallback<H, T>(mut self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.method_not_allowed = Some(MethodRouter::new().fallback(handler));
+        self
+    }
+
+    /// Append the resource to the parent resource when nested with `nest`
+    pub fn inherit_resource(mut self) -> Self {
+        self.inherit_resource = true;
+        self
+    }
+
+    /// Check the roles of the parent when nested with `nest`, unless the
+    /// router has its own roles
+    pub fn inherit_roles(mut self) -> Self {
+        self.inherit_roles = true;
         self
     }
 
@@ -187,160 +834,3089 @@ where
     ///
     /// ```
     pub fn roles(mut self, roles: &[String]) -> Self {
-        self.roles = Some(roles.to_vec());
+        self.roles = RolesHandle::new(Some(roles.to_vec()));
         self
     }
 
-    /// Build guard router and generate axum router
+    /// Set the roles, the caller needs one of them
+    ///
+    /// The whole list is passed to one `OnGuard::on_roles` call, so the guard
+    /// decides whether the caller holds one of them, like [`Requirement::Any`].
     ///
     /// # Example
     ///
     /// ```rust,ignore
-    ///  async fn handler1() {}
-    ///  async fn handler2() {}
-    ///  let guard_router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
-    ///     .action("my:create", "/admin", post(handler))
-    ///     .action("my:update", "/admin", put(handler2))
-    ///     .build();
+    ///  let router = GuardRouter::new("my:router:report", Arc::new(MyGuard))
+    ///     .roles_any(&["admin".to_string(), "auditor".to_string()])
+    ///     .action("my:get", "/report", get(handler1));
+    /// ```
+    pub fn roles_any(mut self, roles: &[String]) -> Self {
+        self.requirement = Requirement::Any;
+        self.roles(roles)
+    }
+
+    /// Set the roles, the caller needs all of them
     ///
-    ///  let app = Router::new().nest("/protect", guard_router);
+    /// Every role is checked with its own `OnGuard::on_roles` call, so a guard
+    /// checking a single role works as is, like [`Requirement::All`].
+    ///
+    /// # Example
     ///
+    /// ```rust,ignore
+    ///  let router = GuardRouter::new("my:router:payout", Arc::new(MyGuard))
+    ///     .roles_all(&["finance".to_string(), "approver".to_string()])
+    ///     .action("my:approve", "/payouts", post(handler1));
     /// ```
-    pub fn build(&self) -> Router<S> {
-        let mut router = Router::<S>::new();
-        for (path, action) in &self.actions {
-            let mut method_router = MethodRouter::new();
-            for (name, r) in action.routers() {
-                method_router = method_router.merge(
-                    r.layer(
-                        GuardActionLayer::new(self.guard.clone(), &self.resource, &name)
-                            .roles(&self.roles),
-                    ),
-                );
-            }
-            router = router.route(path, method_router);
-        }
-        router
+    pub fn roles_all(mut self, roles: &[String]) -> Self {
+        self.requirement = Requirement::All;
+        self.roles(roles)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::sync::Arc;
 
-    use crate::test_helper::{TestClient, TestGuard};
-    use crate::{action, router::GuardRouter};
-    use axum::routing::{get, post};
-    use axum::Router;
-    use reqwest::StatusCode;
+    /// Get the handle of the router roles to replace them at runtime
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .roles(&["admin".to_string()]);
+    ///  let roles = router.roles_handle();
+    ///  let app = router.action("my:get", "/admin", get(handler1)).build();
+    ///
+    ///  // the built router now requires the user role
+    ///  roles.set(&["user".to_string()]);
+    /// ```
+    pub fn roles_handle(&self) -> RolesHandle {
+        self.roles.clone()
+    }
 
-    #[test]
-    fn test_guard_new() {
-        let guid = Arc::new(TestGuard::new());
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid);
-        assert_eq!(router.resource, "my:test");
+    /// Configure the guard router with a single policy
+    ///
+    /// The policy resource, roles and requirement replace the router ones.
+    /// When the policy action is not empty, it is checked for every action
+    /// of the router instead of the action names.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum_guard_router::{action, GuardRouter, Policy, Requirement};
+    ///
+    ///  async fn handler1() {}
+    ///  async fn handler2() {}
+    ///
+    ///  let policy = Policy::new("my:router:admin", "my:manage")
+    ///     .roles(&["admin".to_string()])
+    ///     .requirement(Requirement::All);
+    ///  let router = GuardRouter::new("my:router", Arc::new(MyGuard))
+    ///     .with_policy(policy)
+    ///     .route("/admin", action::get("my:get", handler1).put("my:update", handler2));
+    ///
+    /// ```
+    #[track_caller]
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        validate_name("resource", &policy.resource);
+        self.resource = policy.resource;
+        self.roles = RolesHandle::new(policy.roles);
+        self.requirement = policy.requirement;
+        self.policy_action = Some(policy.action).filter(|action| !action.is_empty());
+        self
     }
 
-    #[test]
-    fn test_guard_action() {
-        let guid = Arc::new(TestGuard::new());
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
-            .action("action1", "/", get(handler))
-            .action("action2", "/test", post(handler2));
-        assert_eq!(router.actions.len(), 2);
+    /// Read the enforcement mode of the router resource from a runtime registry
+    ///
+    /// The registry is consulted on every request, resources without a mode
+    /// use the registry default.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum_guard_router::{Enforcement, GuardConfig};
+    ///
+    ///  async fn handler1() {}
+    ///
+    ///  let config = Arc::new(GuardConfig::new());
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .config(config.clone())
+    ///     .action("my:get", "/admin", get(handler1));
+    ///
+    ///  // turn off the guard for the resource in staging
+    ///  config.set("my:router:admin", Enforcement::Off);
+    /// ```
+    pub fn config(mut self, config: Arc<GuardConfig>) -> Self {
+        self.config = Some(config);
+        self
+    }
 
-        assert_eq!(router.actions[0].0, "/");
-        // assert_eq!(router.actions[0].1, "action1");
-        assert_eq!(router.actions[1].0, "/test");
-        // assert_eq!(router.actions[1].0, "action2");
+    /// Add a `Server-Timing` header with the `guard` and `roles` check durations
+    /// in milliseconds to the allowed responses
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .server_timing()
+    ///     .action("my:get", "/admin", get(handler1));
+    ///
+    ///  // Server-Timing: guard;dur=0.120, roles;dur=0.031
+    /// ```
+    pub fn server_timing(mut self) -> Self {
+        self.server_timing = true;
+        self
     }
 
-    #[tokio::test]
+    /// Pass the requests matching the predicate to the handlers without the
+    /// guard, like the requests of an internal service
+    ///
+    /// The predicate runs before the guard hooks, like the `Enforcement::Off`
+    /// mode, so none of them is called. It only reads the request head, the
+    /// peer address is in the `ConnectInfo` extension when the app is served
+    /// with it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .skip_if(|parts| parts.headers.get("x-internal-token") == Some(&token))
+    ///     .action("my:get", "/admin", get(handler1));
+    /// ```
+    pub fn skip_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        self.skip_if = Some(SkipIf(Arc::new(predicate)));
+        self
+    }
+
+    /// Pass the CORS preflight requests to the handlers without the guard
+    ///
+    /// A preflight is an `OPTIONS` request with the `Access-Control-Request-Method`
+    /// header, which the browser sends without the credentials. It is opt-in,
+    /// the other `OPTIONS` requests are still guarded.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn cors() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .skip_preflight(true)
+    ///     .route("/admin", action::post("my:create", handler1).options("my:cors", cors));
+    /// ```
+    pub fn skip_preflight(mut self, skip_preflight: bool) -> Self {
+        self.skip_preflight = skip_preflight;
+        self
+    }
+
+    /// Run the same checks once per request across the cached guard layers
+    ///
+    /// The static roles and the allowed resource and action are kept in the
+    /// request, a nested router or layer with `cached` reuses them instead of
+    /// calling the guard again. The denials are not kept, they end the request.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .cached(true)
+    ///     .action("my:get", "/admin", get(handler1));
+    /// ```
+    pub fn cached(mut self, cached: bool) -> Self {
+        self.cached = cached;
+        self
+    }
+
+    /// Guard the router with the roles only, for the routes without a
+    /// meaningful resource and action
+    ///
+    /// The roles are checked with `on_roles`, or `on_roles_for_request`
+    /// without static roles, and their success allows the request, the
+    /// resource and action hooks are never called.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:ops", Arc::new(MyGuard))
+    ///     .roles(&["ops".to_string()])
+    ///     .roles_only()
+    ///     .action("my:metrics", "/metrics", get(handler1));
+    /// ```
+    pub fn roles_only(mut self) -> Self {
+        self.roles_only = true;
+        self
+    }
+
+    /// Add diagnostic headers to the deny responses, like in staging
+    ///
+    /// The `X-Guard-Resource` and `X-Guard-Action` headers have the checked
+    /// resource and action, and `X-Guard-Reason` has `roles` or `resource`
+    /// for the check which denied. The status and body are not changed.
+    /// It is off by default, not to expose the permissions in production.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .debug_headers()
+    ///     .action("my:get", "/admin", get(handler1));
+    /// ```
+    pub fn debug_headers(mut self) -> Self {
+        self.debug_headers = true;
+        self
+    }
+
+    /// Buffer the request body for the guard, like to deny changing the `role`
+    /// field of a user unless the caller is an admin
+    ///
+    /// The body is read up to the limit in bytes before the checks, the guard
+    /// reads it with `GuardContext::body` and the handler gets it unchanged.
+    /// A larger body is answered with `413 Payload Too Large`, and a body
+    /// failing to be read with `400 Bad Request`. It is off by default, as
+    /// the whole body is held in memory.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .buffer_body(64 * 1024)
+    ///     .action("my:update", "/user", put(handler1));
+    /// ```
+    pub fn buffer_body(mut self, limit: usize) -> Self {
+        self.body_limit = Some(limit);
+        self
+    }
+
+    /// Buffer the allowed responses for `OnGuard::on_response_body`, like to
+    /// redact the fields of a JSON body the caller is not allowed to read
+    ///
+    /// The body is read up to the limit in bytes after the handler, so the
+    /// guard can rewrite it. A larger body can not be filtered and is answered
+    /// with `500 Internal Server Error` rather than sent unfiltered. It is off
+    /// by default, as the whole body is held in memory.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .filter_response(1024 * 1024)
+    ///     .action("my:get", "/user", get(handler1));
+    /// ```
+    pub fn filter_response(mut self, limit: usize) -> Self {
+        self.response_limit = Some(limit);
+        self
+    }
+
+    /// Set the timeout of the guard checks, like for a slow policy service
+    ///
+    /// The roles and the resource and action checks of a request must finish
+    /// in time, otherwise `OnGuard::on_error` is called and the request is
+    /// handled with the fail mode of [`GuardRouter::on_failure`], denied with
+    /// `503 Service Unavailable` by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .guard_timeout(Duration::from_millis(500))
+    ///     .action("my:get", "/user", get(handler1));
+    /// ```
+    #[cfg(feature = "timeout")]
+    pub fn guard_timeout(mut self, timeout: Duration) -> Self {
+        self.guard_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how the guard errors are handled, when the guard returns
+    /// [`Decision::Error`](crate::Decision::Error) like for a timeout of the
+    /// policy service
+    ///
+    /// With [`FailMode::Closed`], the default, the request is denied with
+    /// `503 Service Unavailable`. With [`FailMode::Open`], it is allowed.
+    /// `OnGuard::on_error` is called in both modes.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum_guard_router::FailMode;
+    ///
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:catalog", Arc::new(MyGuard))
+    ///     .on_failure(FailMode::Open)
+    ///     .action("my:list", "/products", get(handler1));
+    /// ```
+    pub fn on_failure(mut self, fail_mode: FailMode) -> Self {
+        self.fail_mode = fail_mode;
+        self
+    }
+
+    /// Build the deny responses of the router in one place
+    ///
+    /// The guard still decides, its deny response is passed in the reason
+    /// with the check which denied, and the function returns the response
+    /// sent to the client. The pending approvals are not passed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum_guard_router::{DenyKind, DenyReason};
+    ///
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .on_deny(|reason: DenyReason| {
+    ///         let status = reason.response.status();
+    ///         let body = format!(r#"{{"error":"{}","action":"{}"}}"#, reason.kind.as_str(), reason.action);
+    ///         (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+    ///     })
+    ///     .action("my:get", "/admin", get(handler1));
+    /// ```
+    pub fn on_deny<F>(mut self, on_deny: F) -> Self
+    where
+        F: Fn(DenyReason) -> Response + Send + Sync + 'static,
+    {
+        self.on_deny = Some(OnDeny(Arc::new(on_deny)));
+        self
+    }
+
+    /// Read the request id from the header or generate one
+    ///
+    /// The id is stored in the request extensions as `RequestId`, available to
+    /// the guard hooks with `GuardContext::request_id`, passed to `on_allowed`,
+    /// `on_response` and `on_audit`, and echoed in the same response header. Nested guard routers reuse the id of the outer one.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use axum::http::HeaderName;
+    ///
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .with_request_id(HeaderName::from_static("x-request-id"))
+    ///     .action("my:get", "/admin", get(handler1));
+    /// ```
+    pub fn with_request_id(mut self, header: HeaderName) -> Self {
+        self.request_id = Some(header);
+        self
+    }
+
+    /// Serve the cached handler responses of the `GET` requests
+    ///
+    /// The guard still runs for every request, when it allows and the cache
+    /// has a live response for the key, the handler is skipped. The misses
+    /// call the handler and cache the successful responses for `ttl`. The key
+    /// function returns `None` to bypass the cache, the keys are kept apart
+    /// per action.
+    ///
+    /// A cached response is served to every caller allowed with the same key,
+    /// so the key includes the caller when the response depends on it. The
+    /// responses setting a cookie or marked `Cache-Control: private` or
+    /// `no-store` are not cached, nor the bodies over 1 MiB, and at most 1024
+    /// responses are kept.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  use std::time::Duration;
+    ///
+    ///  async fn handler1() {}
+    ///
+    ///  // the report of each user is cached apart
+    ///  let router = GuardRouter::new("my:router:report", Arc::new(MyGuard))
+    ///     .response_cache(Duration::from_secs(30), |parts| {
+    ///         let user = parts.headers.get("x-user")?.to_str().ok()?;
+    ///         Some(format!("{user}:{}", parts.uri))
+    ///     })
+    ///     .action("my:get", "/report", get(handler1));
+    /// ```
+    pub fn response_cache<F>(mut self, ttl: Duration, key_fn: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.cache = Some(Arc::new(ResponseCache::new(ttl, key_fn)));
+        self
+    }
+
+    /// Apply a tower layer to every guarded action, like a compression or a
+    /// timeout layer
+    ///
+    /// The layer wraps the guard layer of each action and the fallback, so it
+    /// also handles the denied requests. The layers added later are outer,
+    /// like `axum::Router::layer`, and the nested routers keep their own.
+    /// See `GuardRouter::route_layer` for the layers after the guard.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:report", Arc::new(MyGuard))
+    ///     .layer(CompressionLayer::new())
+    ///     .action("my:get", "/report", get(handler1));
+    /// ```
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers.push(Arc::new(move |router: MethodRouter<S>| {
+            router.layer(layer.clone())
+        }));
+        self
+    }
+
+    /// Apply a tower layer to every guarded action after the guard, like a
+    /// rate limit per identity
+    ///
+    /// The layer wraps the handler inside the guard layer, so it only runs
+    /// for the allowed requests and sees the request as rewritten by
+    /// `OnGuard::on_request_mut`. From the outside in, a request passes the
+    /// `layer` layers, the guard, then the `route_layer` layers, and the
+    /// route layers added later are outer.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:report", Arc::new(MyGuard))
+    ///     .route_layer(middleware::from_fn(rate_limit_by_user))
+    ///     .action("my:get", "/report", get(handler1));
+    /// ```
+    pub fn route_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.route_layers
+            .push(Arc::new(move |router: MethodRouter<S>| {
+                router.layer(layer.clone())
+            }));
+        self
+    }
+
+    /// List the guarded endpoints of the router, with the resource, action
+    /// and roles the guard checks
+    ///
+    /// The methods are only known for the actions added with a method filter,
+    /// like `action::get` or `action_with_decision`, the ones added with a
+    /// method router or `action::any` have no methods. The actions of the
+    /// nested routers follow, with the nest path prefixed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn handler2() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:user", Arc::new(MyGuard))
+    ///     .route("/user", action::get("my:get", handler1).post("my:create", handler2));
+    ///
+    ///  for entry in router.manifest() {
+    ///      println!("{:?} {} => {}", entry.methods, entry.path, entry.action);
+    ///  }
+    /// ```
+    pub fn manifest(&self) -> Vec<GuardEntry> {
+        let roles = self.roles.get();
+        self.actions
+            .iter()
+            .flat_map(|(path, action)| {
+                action.routes().iter().map(|entry| GuardEntry {
+                    resource: self.resource.clone(),
+                    action: self
+                        .policy_action
+                        .as_ref()
+                        .unwrap_or(&entry.name)
+                        .to_string(),
+                    path: path.to_string(),
+                    methods: entry.filter.map(filter_methods).unwrap_or_default(),
+                    roles: entry.roles.clone().or_else(|| roles.clone()),
+                })
+            })
+            .chain(self.nested_entries.iter().cloned())
+            .collect()
+    }
+
+    /// Check the resource and action names with the naming convention of
+    /// the team when the router is built, an error panics with its message
+    ///
+    /// The names are always checked to be non-empty and without whitespace
+    /// where they are added.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .validate_names(|name| match name.split(':').count() >= 2 {
+    ///         true => Ok(()),
+    ///         false => Err(format!("{name} is not `segment:segment`")),
+    ///     })
+    ///     .action("my:get", "/admin", get(handler1))
+    ///     .build();
+    /// ```
+    pub fn validate_names<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.name_validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Build guard router and generate axum router
+    ///
+    /// It panics when a name is rejected by `validate_names` or the manifest
+    /// is rejected by `OnGuard::validate`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///  async fn handler2() {}
+    ///  let guard_router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .action("my:create", "/admin", post(handler))
+    ///     .action("my:update", "/admin", put(handler2))
+    ///     .build();
+    ///
+    ///  let app = Router::new().nest("/protect", guard_router);
+    ///
+    /// ```
+    #[track_caller]
+    pub fn build(&self) -> Router<S> {
+        self.clone().into_router()
+    }
+
+    /// Build guard router and generate axum router, the actions are moved
+    /// into the router instead of being cloned
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  async fn handler1() {}
+    ///
+    ///  let guard_router = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .action("my:get", "/admin", get(handler1))
+    ///     .into_router();
+    ///
+    /// ```
+    #[track_caller]
+    pub fn into_router(mut self) -> Router<S> {
+        if let Some(validator) = &self.name_validator {
+            let names = self
+                .actions
+                .iter()
+                .map(|(_, action)| action)
+                .chain(&self.fallback)
+                .flat_map(|action| action.routes())
+                .flat_map(|entry| std::iter::once(&entry.name).chain(&entry.candidates));
+            for name in std::iter::once(&self.resource).chain(names) {
+                if let Err(err) = validator(name) {
+                    panic!("invalid guard name {name:?}: {err}");
+                }
+            }
+        }
+        if let Err(err) = self.guard.validate(&self.manifest()) {
+            panic!("invalid guard manifest of {:?}: {err}", self.resource);
+        }
+        // the actions and the public routes of a same path are merged into
+        // one method router, so the path is only routed once
+        let mut paths: Vec<(String, MethodRouter<S>)> = Vec::new();
+        // the paths accepting every method keep their own method fallback
+        let any_paths: Vec<String> = self
+            .actions
+            .iter()
+            .filter(|(_, action)| action.routes().iter().any(|entry| entry.any))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let guarded: Vec<_> = std::mem::take(&mut self.actions)
+            .into_iter()
+            .flat_map(|(path, action)| {
+                let routes = action.into_routers();
+                routes.into_iter().map(move |entry| (path.clone(), entry))
+            })
+            .map(|(path, entry)| (path, self.guarded(entry)))
+            .collect();
+        let public = std::mem::take(&mut self.public);
+        for (path, route) in guarded.into_iter().chain(public) {
+            let index = match paths.iter().position(|(p, _)| *p == path) {
+                Some(index) => index,
+                None => {
+                    let method_router = match &self.method_not_allowed {
+                        Some(fallback) if !any_paths.contains(&path) => fallback.clone(),
+                        _ => MethodRouter::new(),
+                    };
+                    paths.push((path, method_router));
+                    paths.len() - 1
+                }
+            };
+            let method_router = std::mem::take(&mut paths[index].1);
+            paths[index].1 = method_router.merge(route);
+        }
+
+        let mut router = paths
+            .into_iter()
+            .fold(Router::<S>::new(), |router, (path, method_router)| {
+                router.route(&path, method_router)
+            });
+        if let Some(fallback) = self.fallback.take() {
+            for entry in fallback.into_routers() {
+                router = router.fallback(self.guarded(entry));
+            }
+        }
+        std::mem::take(&mut self.nested)
+            .into_iter()
+            .fold(router, |router, (path, child)| router.nest(&path, child))
+    }
+
+    /// Guard the method router of an action with the router options
+    fn guarded(&self, entry: ActionRoute<S, Infallible>) -> MethodRouter<S> {
+        let name = self.policy_action.as_ref().unwrap_or(&entry.name);
+        let layer = GuardActionLayer::new(self.guard.clone(), &self.resource, name)
+            .requirement(self.requirement)
+            .config(&self.config)
+            .server_timing(self.server_timing)
+            .skip_preflight(self.skip_preflight)
+            .cached(self.cached)
+            .roles_only(self.roles_only)
+            .debug_headers(self.debug_headers)
+            .fail_mode(self.fail_mode)
+            .body_limit(self.body_limit)
+            .response_limit(self.response_limit)
+            .deny_handler(&self.on_deny)
+            .skip_predicate(&self.skip_if)
+            .request_id(&self.request_id)
+            .csp(&entry.csp)
+            .candidates(match self.policy_action {
+                // the policy action is checked instead
+                Some(_) => &[],
+                None => &entry.candidates,
+            })
+            .cache(&self.cache)
+            .limit(&entry.limit);
+        #[cfg(feature = "timeout")]
+        let layer = layer.guard_timeout(self.guard_timeout);
+        // the action roles override the router roles
+        let layer = match entry.roles {
+            Some(_) => layer.roles(&entry.roles),
+            None => layer.roles_handle(&self.roles),
+        };
+        let router = self
+            .route_layers
+            .iter()
+            .fold(entry.router, |router, layer| layer(router));
+        self.layers
+            .iter()
+            .fold(router.layer(layer), |router, layer| layer(router))
+    }
+
+    /// Provide the state of the handlers and build the guard router, like
+    /// `axum::Router::with_state`
+    ///
+    /// The guard reads the same state with `GuardContext::router_state` in
+    /// its request hooks, before the handler runs. The state given later,
+    /// like with the `with_state` of an outer router, only reaches the
+    /// handlers.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    ///  #[derive(Clone)]
+    ///  struct AppState { name: String }
+    ///
+    ///  async fn handler1(State(state): State<AppState>) -> String {
+    ///      state.name
+    ///  }
+    ///
+    ///  impl OnGuard for MyGuard {
+    ///      async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+    ///          let state = ctx.router_state::<AppState>().unwrap();
+    ///          // check with the state
+    ///          Ok(())
+    ///      }
+    ///  }
+    ///
+    ///  let app = GuardRouter::new("my:router:admin", Arc::new(MyGuard))
+    ///     .action("my:get", "/admin", get(handler1))
+    ///     .with_state(AppState { name: "app".to_string() });
+    ///
+    /// ```
+    #[track_caller]
+    pub fn with_state(self, state: S) -> Router {
+        self.into_router()
+            .layer(Extension(GuardState(state.clone())))
+            .with_state(state)
+    }
+}
+
+/// Build the guard router into an axum router with `into`, like
+/// `Router::new().nest("/user", guard_router.into())`
+impl<G, S> From<GuardRouter<G, S>> for Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    G: OnGuard + Clone + Send + Sync + 'static,
+{
+    #[track_caller]
+    fn from(router: GuardRouter<G, S>) -> Self {
+        router.into_router()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::action::Action;
+    use crate::test_helper::{TestClient, TestGuard};
+    use crate::{
+        action, guard, router::GuardRouter, Decision, DenyKind, DenyReason, Enforcement, FailMode,
+        GuardConfig, GuardContext, GuardInfo, OnGuard, Policy, QueryLimits, Requirement,
+    };
+    use axum::body::Bytes;
+    use axum::extract::{Path, State};
+    use axum::http::{response::Parts, Extensions, HeaderName, HeaderValue, Method};
+    use axum::response::{Html, IntoResponse, Redirect, Response};
+    use axum::routing::{get, post, MethodFilter};
+    use axum::Router;
+    use axum::{Extension, Json};
+    use reqwest::StatusCode;
+    use std::collections::BTreeMap;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+    use tower::service_fn;
+
+    #[test]
+    fn test_guard_new() {
+        let guid = Arc::new(TestGuard::new());
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid);
+        assert_eq!(router.resource, "my:test");
+    }
+
+    #[test]
+    fn test_guard_action() {
+        let guid = Arc::new(TestGuard::new());
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .action("action1", "/", get(handler))
+            .action("action2", "/test", post(handler2));
+        assert_eq!(router.actions.len(), 2);
+
+        assert_eq!(router.actions[0].0, "/");
+        // assert_eq!(router.actions[0].1, "action1");
+        assert_eq!(router.actions[1].0, "/test");
+        // assert_eq!(router.actions[1].0, "action2");
+    }
+
+    #[derive(Clone)]
+    struct CandidateGuard;
+
+    impl OnGuard for CandidateGuard {
+        async fn on_guard(&self, _resource: &str, action: &str) -> Result<(), Response> {
+            match action {
+                "read:all" => Ok(()),
+                _ => Err(StatusCode::FORBIDDEN.into_response()),
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct PickGuard;
+
+    impl OnGuard for PickGuard {
+        async fn on_guard_candidates(
+            &self,
+            _ctx: &GuardContext<'_>,
+            candidates: &[String],
+        ) -> Result<usize, Response> {
+            assert_eq!(candidates, ["read:own", "read:all"]);
+            Ok(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_nest_inherit_roles() {
+        let guard = TestGuard::new_with(true, false);
+        let admin = vec!["admin".to_string()];
+        let profile = GuardRouter::new(":profile", Arc::new(guard.clone()))
+            .inherit_resource()
+            .inherit_roles()
+            .action("action1", "/profile", get(handler));
+        let other = GuardRouter::new("my:other", Arc::new(guard.clone())).action(
+            "action2",
+            "/other",
+            get(handler),
+        );
+        let parent = GuardRouter::new("admin:user", Arc::new(guard.clone())).roles(&admin);
+        let handle = parent.roles_handle();
+        let router = parent.nest("/user", profile).nest("/user2", other);
+        assert_eq!(router.manifest()[0].roles, Some(admin));
+
+        let client = TestClient::new(router.build());
+        assert_eq!(
+            client.get("/user/profile").await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(client.get("/user2/other").await.status(), StatusCode::OK);
+        // the parent roles are shared with the child
+        handle.clear();
+        assert_eq!(client.get("/user/profile").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_nest_inherit_resource() {
+        let guard = TestGuard::new_with(true, true);
+        let profile = GuardRouter::new(":profile", Arc::new(guard.clone()))
+            .inherit_resource()
+            .action("action1", "/profile", get(handler));
+        let other = GuardRouter::new("my:other", Arc::new(guard.clone())).action(
+            "action2",
+            "/other",
+            get(handler),
+        );
+        let router = GuardRouter::new("admin:user", Arc::new(guard.clone()))
+            .action("action3", "/test", get(handler))
+            .nest("/user", profile)
+            .nest("/user2", other);
+        assert_eq!(
+            router
+                .manifest()
+                .into_iter()
+                .map(|entry| (entry.resource, entry.path))
+                .collect::<Vec<_>>(),
+            vec![
+                ("admin:user".to_string(), "/test".to_string()),
+                (
+                    "admin:user:profile".to_string(),
+                    "/user/profile".to_string()
+                ),
+                ("my:other".to_string(), "/user2/other".to_string()),
+            ]
+        );
+
+        let client = TestClient::new(router.build());
+        assert_eq!(client.get("/user/profile").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/user2/other").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:admin:user:profile:action1",
+                "guard:my:other:action2",
+                "guard:admin:user:action3"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the action name is empty")]
+    fn test_guard_empty_action_panic() {
+        let _ = GuardRouter::<_, ()>::new("my:test", Arc::new(TestGuard::new())).action(
+            "",
+            "/test",
+            get(handler),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the action name \"my get\" contains whitespace")]
+    fn test_guard_whitespace_action_panic() {
+        let _ = GuardRouter::<_, ()>::new("my:test", Arc::new(TestGuard::new()))
+            .route("/test", action::get("my get", handler));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid guard name \"action1\": not segment:segment")]
+    fn test_guard_validate_names_panic() {
+        let _ = GuardRouter::<_, ()>::new("my:test", Arc::new(TestGuard::new()))
+            .validate_names(|name| match name.contains(':') {
+                true => Ok(()),
+                false => Err("not segment:segment".to_string()),
+            })
+            .action("my:get", "/test", get(handler))
+            .action("action1", "/test2", get(handler))
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown action action2")]
+    fn test_guard_validate_manifest() {
+        #[derive(Clone)]
+        struct StoreGuard;
+
+        impl OnGuard for StoreGuard {
+            fn validate(&self, manifest: &[crate::GuardEntry]) -> Result<(), String> {
+                match manifest.iter().find(|entry| entry.action != "action1") {
+                    Some(entry) => Err(format!("unknown action {}", entry.action)),
+                    None => Ok(()),
+                }
+            }
+        }
+
+        let router = GuardRouter::<_, ()>::new("my:test", Arc::new(StoreGuard)).action(
+            "action1",
+            "/test",
+            get(handler),
+        );
+        let _ = router.build();
+        let _ = router.action("action2", "/test2", get(handler)).build();
+    }
+
+    #[test]
+    fn test_guard_validate_names() {
+        let _ = GuardRouter::<_, ()>::new("my:test", Arc::new(TestGuard::new()))
+            .validate_names(|name| match name.contains(':') {
+                true => Ok(()),
+                false => Err("not segment:segment".to_string()),
+            })
+            .route(
+                "/test",
+                action::get("my:get", handler).post("my:create", handler2),
+            )
+            .build();
+    }
+
+    #[tokio::test]
+    async fn test_guard_debug_headers() {
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new()))
+            .debug_headers()
+            .action("action1", "/test", get(handler))
+            .action_with_roles("action2", "/admin", &["admin".to_string()], get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["x-guard-resource"], "my:test");
+        assert_eq!(response.headers()["x-guard-action"], "action1");
+        assert_eq!(response.headers()["x-guard-reason"], "resource");
+        let response = client.get("/admin").await;
+        assert_eq!(response.headers()["x-guard-action"], "action2");
+        assert_eq!(response.headers()["x-guard-reason"], "roles");
+
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new()))
+            .action("action1", "/test", get(handler))
+            .build();
+        let response = TestClient::new(router).get("/test").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response.headers().contains_key("x-guard-resource"));
+        assert!(!response.headers().contains_key("x-guard-reason"));
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_methods() {
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone())).route(
+            "/test",
+            action::on(MethodFilter::GET.or(MethodFilter::HEAD), "read", handler)
+                .post("write", handler2),
+        );
+        assert_eq!(
+            router.manifest()[0].methods,
+            vec![Method::GET, Method::HEAD]
+        );
+
+        let client = TestClient::new(router.build());
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.head("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/test").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:read",
+                "guard:my:test:read",
+                "guard:my:test:write"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_fallback() {
+        async fn not_found() -> StatusCode {
+            StatusCode::NOT_FOUND
+        }
+
+        let guard = TestGuard::new();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(handler))
+            .fallback("unknown", not_found)
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/missing").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(guard.calls(), vec!["guard:my:test:unknown"]);
+
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new_with(true, true)))
+            .fallback("unknown", not_found)
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(
+            client.post("/missing").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_fallback_service() {
+        let not_found = service_fn(|_: axum::extract::Request| async {
+            Ok::<_, Infallible>(StatusCode::NOT_FOUND.into_response())
+        });
+
+        let guard = TestGuard::new();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(handler))
+            .fallback_service("unknown", not_found)
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/missing").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(guard.calls(), vec!["guard:my:test:unknown"]);
+
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new_with(true, true)))
+            .fallback_service("unknown", not_found)
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(
+            client.post("/missing").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_method_not_allowed_fallback() {
+        async fn method_not_allowed() -> (StatusCode, Json<serde_json::Value>) {
+            let body = serde_json::json!({ "error": "method not allowed" });
+            (StatusCode::METHOD_NOT_ALLOWED, Json(body))
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new_with(true, true)))
+            .method_not_allowed_fallback(method_not_allowed)
+            .action("action1", "/test", get(handler))
+            .route(
+                "/test2",
+                action::post("action2", handler).any("action3", handler2),
+            )
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        let response = client.post("/test").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.text().await, r#"{"error":"method not allowed"}"#);
+        // the any action is the fallback of its path
+        assert_eq!(client.put("/test2").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_service() {
+        let echo = service_fn(|request: axum::extract::Request| async move {
+            Ok::<_, Infallible>(request.method().to_string().into_response())
+        });
+
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action_service("action1", "/proxy", echo)
+            .route(
+                "/file",
+                action::get_service("action2", echo).put("action3", handler),
+            )
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.post("/proxy").await.text().await, "POST");
+        assert_eq!(client.get("/file").await.text().await, "GET");
+        assert_eq!(client.put("/file").await.status(), StatusCode::OK);
+        assert_eq!(
+            client.post("/file").await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            guard.calls()[..3],
+            [
+                "guard:my:test:action1",
+                "guard:my:test:action2",
+                "guard:my:test:action3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_public_route() {
+        let guard = TestGuard::new();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(handler))
+            .public_route("/test", post(handler2))
+            .public_route("/test/health", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(client.post("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test/health").await.status(), StatusCode::OK);
+        assert_eq!(guard.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_skip_if() {
+        let guard = TestGuard::new();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .skip_if(|parts| parts.headers.contains_key("x-internal"))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::FORBIDDEN);
+        let status = client.get("/test").header("x-internal", "1").await.status();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(guard.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_on() {
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .route(
+                "/test",
+                action::on(MethodFilter::GET.or(MethodFilter::POST), "action1", handler).on(
+                    MethodFilter::PUT,
+                    "action2",
+                    handler2,
+                ),
+            )
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.put("/test").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:action1",
+                "guard:my:test:action1",
+                "guard:my:test:action2"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_merge() {
+        let guard = TestGuard::new_with(true, true);
+        let read = action::get("action1", handler);
+        let write = action::post("action2", handler2).put("action3", handler);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .route("/test", read.merge(write))
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.put("/test").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:action1",
+                "guard:my:test:action2",
+                "guard:my:test:action3"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot merge actions with the same method POST")]
+    fn test_guard_action_merge_same_method() {
+        let read: Action = action::get("action1", handler).post("action2", handler2);
+        let _ = read.merge(action::post("action3", handler2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot merge actions both accepting any method")]
+    fn test_guard_action_merge_any() {
+        let read: Action = action::any("action1", handler);
+        let _ = read.merge(action::get("action2", handler).any("action3", handler2));
+    }
+
+    #[tokio::test]
+    async fn test_guard_resource() {
+        use crate::action::CrudHandlers;
+
+        async fn show(Path(id): Path<String>) -> String {
+            id
+        }
+
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .resource(
+                "/api/users/",
+                CrudHandlers::new()
+                    .index(handler)
+                    .show(show)
+                    .create(handler)
+                    .update(handler)
+                    .delete(handler),
+            )
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/api/users").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/api/users").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/api/users/1").await.text().await, "1");
+        assert_eq!(client.put("/api/users/1").await.status(), StatusCode::OK);
+        assert_eq!(client.delete("/api/users/1").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:users:list",
+                "guard:my:test:users:create",
+                "guard:my:test:users:get",
+                "guard:my:test:users:update",
+                "guard:my:test:users:delete"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot name the CRUD actions of \"\"")]
+    fn test_guard_resource_root_panic() {
+        let guard = Arc::new(TestGuard::new());
+        let _ = GuardRouter::<TestGuard, ()>::new("my:test", guard)
+            .resource("/", crate::action::CrudHandlers::new().index(handler));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot name the CRUD actions of \"/users/:id\"")]
+    fn test_guard_resource_param_panic() {
+        let guard = Arc::new(TestGuard::new());
+        let _ = GuardRouter::<TestGuard, ()>::new("my:test", guard).resource(
+            "/users/:id/",
+            crate::action::CrudHandlers::new().index(handler),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_roles_only() {
+        let guard = TestGuard::new_with(false, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .roles(&["admin".to_string()])
+            .roles_only()
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(guard.calls(), vec!["roles:admin"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_route_with_roles() {
+        let guard = TestGuard::new_with(true, false);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .roles(&["user".to_string()])
+            .route_with_roles(
+                "/admin",
+                &["admin".to_string()],
+                action::get("action1", handler).post("action2", handler2),
+            )
+            .route_with_roles(
+                "/editor",
+                &["editor".to_string()],
+                action::get("action3", handler),
+            )
+            .route("/test", action::get("action4", handler))
+            .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/admin").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(client.post("/admin").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(client.get("/editor").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(client.get("/test").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            guard.calls(),
+            vec!["roles:admin", "roles:admin", "roles:editor", "roles:user"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_deny() {
+        let json = GuardRouter::new("my:test", Arc::new(TestGuard::new()))
+            .on_deny(|reason: DenyReason| {
+                let body = format!(
+                    r#"{{"reason":"{}","action":"{}"}}"#,
+                    reason.kind.as_str(),
+                    reason.action
+                );
+                let status = reason.response.status();
+                (status, [("content-type", "application/json")], body).into_response()
+            })
+            .action("action1", "/api", get(handler))
+            .action_with_roles(
+                "action2",
+                "/api/admin",
+                &["admin".to_string()],
+                get(handler),
+            );
+        let html = GuardRouter::new("my:test", Arc::new(TestGuard::new()))
+            .on_deny(|reason: DenyReason| {
+                assert_eq!(reason.kind, DenyKind::Resource);
+                Redirect::to("/login").into_response()
+            })
+            .action("action1", "/page", get(handler));
+        let client = TestClient::new(json.build().merge(html.build()));
+
+        let response = client.get("/api").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(
+            response.text().await,
+            r#"{"reason":"resource","action":"action1"}"#
+        );
+        let response = client.get("/api/admin").await;
+        assert_eq!(
+            response.text().await,
+            r#"{"reason":"roles","action":"action2"}"#
+        );
+
+        let response = client.get("/page").await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "/login");
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_denied() {
+        #[derive(Clone)]
+        struct EnvelopeGuard;
+        impl OnGuard for EnvelopeGuard {
+            async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
+                Err(StatusCode::FORBIDDEN.into_response())
+            }
+
+            fn on_denied(&self, reason: DenyReason) -> Response {
+                let body = format!(
+                    r#"{{"error":"{}","trace_id":"{}"}}"#,
+                    reason.kind.as_str(),
+                    reason.request_id.unwrap_or_default()
+                );
+                let status = reason.response.status();
+                (status, [("content-type", "application/json")], body).into_response()
+            }
+        }
+
+        let guard = guard::chain(TestGuard::new_with(true, true), EnvelopeGuard);
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .with_request_id(HeaderName::from_static("x-request-id"))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let response = client.get("/test").header("x-request-id", "abc").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(
+            response.text().await,
+            r#"{"error":"resource","trace_id":"abc"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_skip_preflight() {
+        let guard = TestGuard::new();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .skip_preflight(true)
+            .route(
+                "/test",
+                action::post("action1", handler).options("action2", handler),
+            )
+            .build();
+        let client = TestClient::new(router);
+
+        let status = client
+            .options("/test")
+            .header("origin", "https://app.example.com")
+            .header("access-control-request-method", "POST")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+        assert!(guard.calls().is_empty());
+
+        assert_eq!(
+            client.options("/test").await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(guard.calls(), vec!["guard:my:test:action2"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_candidates() {
+        async fn chosen_handler(info: GuardInfo) -> String {
+            info.action
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(PickGuard))
+            .action_candidates(&["read:own", "read:all"], "/test", get(chosen_handler))
+            .build();
+        let response = TestClient::new(router).get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "read:all");
+
+        // the default decides the candidates in order
+        let router = GuardRouter::new("my:test", Arc::new(CandidateGuard))
+            .action_candidates(&["read:own", "read:all"], "/test", get(chosen_handler))
+            .action_candidates(&["read:own"], "/own", get(chosen_handler))
+            .build();
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.text().await, "read:all");
+        assert_eq!(client.get("/own").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_guard_manifest() {
+        let roles = vec!["user".to_string()];
+        let admin = vec!["admin".to_string()];
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", Arc::new(TestGuard::new()))
+            .roles(&roles)
+            .route(
+                "/test",
+                action::get("action1", handler).delete_with_roles("action2", &admin, handler2),
+            )
+            .action_with_decision(
+                "action3",
+                "/decision",
+                MethodFilter::PUT,
+                |_: GuardInfo| async {},
+            )
+            .action("action4", "/router", get(handler));
+
+        let entry =
+            |action: &str, path: &str, methods: Vec<Method>, roles: &[String]| crate::GuardEntry {
+                resource: "my:test".to_string(),
+                action: action.to_string(),
+                path: path.to_string(),
+                methods,
+                roles: Some(roles.to_vec()),
+            };
+        assert_eq!(
+            router.manifest(),
+            vec![
+                entry("action1", "/test", vec![Method::GET], &roles),
+                entry("action2", "/test", vec![Method::DELETE], &admin),
+                entry("action3", "/decision", vec![Method::PUT], &roles),
+                entry("action4", "/router", vec![], &roles),
+            ]
+        );
+    }
+
+    #[tokio::test]
     async fn test_guard_route_forbidden() {
         let guid = Arc::new(TestGuard::new());
         let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
             .route(
                 "/test",
-                action::get("action1", handler).post("action2", handler2),
+                action::get("action1", handler).post("action2", handler2),
+            )
+            .build();
+
+        let app = Router::new().nest("/api", router);
+        let client = TestClient::new(app);
+        let status = client.get("/api/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = client.post("/api/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_route_pass() {
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .route(
+                "/test",
+                action::get("action1", handler).post("action2", handler2),
+            )
+            .build();
+
+        let app = Router::new().nest("/api", router);
+        let client = TestClient::new(app);
+        let status = client.get("/api/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+
+        let status = client.post("/api/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_guard_pass() {
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_guard_nest() {
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .action("action1", "/test", get(handler))
+            .build();
+        let app = Router::new().nest("/api", router);
+        let client = TestClient::new(app);
+        let status = client.get("/api/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_guard_on_roles_403() {
+        let guid = Arc::new(TestGuard::new_with(true, false));
+        let roles = vec!["admin".to_string()];
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .roles(&roles)
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_guard_403() {
+        let guid = Arc::new(TestGuard::new_with(false, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_with_policy() {
+        let guard = TestGuard::new_with(true, true);
+        let policy = Policy::new("my:policy", "my:manage")
+            .roles(&["admin".to_string(), "owner".to_string()])
+            .requirement(Requirement::All);
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", Arc::new(guard.clone()))
+            .with_policy(policy)
+            .route("/test", action::get("action1", handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec!["roles:admin", "roles:owner", "guard:my:policy:my:manage"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_with_policy_roles_403() {
+        let guard = TestGuard::new_with(true, false);
+        let policy = Policy::new("my:policy", "").roles(&["admin".to_string()]);
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", Arc::new(guard.clone()))
+            .with_policy(policy)
+            .route("/test", action::get("action1", handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(guard.calls(), vec!["roles:admin"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_config_toggle() {
+        let guid = Arc::new(TestGuard::new());
+        let config = Arc::new(GuardConfig::new());
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .config(config.clone())
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        config.set("my:test", Enforcement::Off);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+
+        config.set("my:test", Enforcement::Enforce);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_config_shadow() {
+        // the passed requests are not described as allowed
+        async fn info_handler(info: Option<Extension<GuardInfo>>) -> String {
+            info.is_some().to_string()
+        }
+
+        let guard = TestGuard::new();
+        let config = Arc::new(GuardConfig::with_default(Enforcement::Shadow));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", Arc::new(guard.clone()))
+            .config(config.clone())
+            .action("action1", "/test", get(info_handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "false");
+        assert_eq!(guard.calls(), vec!["guard:my:test:action1"]);
+
+        config.set("my:test", Enforcement::Off);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "false");
+        assert_eq!(guard.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_guard_pending_202() {
+        #[derive(Clone)]
+        struct PendingGuard;
+        impl OnGuard for PendingGuard {
+            async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+                Decision::Pending(format!("/approvals/{}", ctx.action()))
+            }
+        }
+
+        static CALLED: AtomicBool = AtomicBool::new(false);
+        async fn pending_handler() {
+            CALLED.store(true, Ordering::SeqCst);
+        }
+
+        let router = GuardRouter::<PendingGuard, ()>::new("my:test", Arc::new(PendingGuard))
+            .action("my:delete", "/test", post(pending_handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.post("/test").await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers()["location"], "/approvals/my:delete");
+        assert!(!CALLED.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_guard_challenge_401() {
+        #[derive(Clone)]
+        struct TokenGuard;
+        impl OnGuard for TokenGuard {
+            async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+                match ctx.headers().get("authorization") {
+                    None => Decision::Challenge(r#"Bearer realm="api""#.to_string()),
+                    Some(token) if token == "Bearer admin" => Decision::Allow,
+                    Some(_) => Decision::Deny(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(TokenGuard))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()["www-authenticate"],
+            r#"Bearer realm="api""#
+        );
+
+        let status = client
+            .get("/test")
+            .header("authorization", "Bearer guest")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client
+            .get("/test")
+            .header("authorization", "Bearer admin")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_authenticate_401_before_403() {
+        #[derive(Clone)]
+        struct TokenGuard;
+        impl OnGuard for TokenGuard {
+            async fn on_authenticate(&self, ctx: &GuardContext<'_>) -> Result<(), String> {
+                match ctx.headers().get("authorization") {
+                    Some(_) => Ok(()),
+                    None => Err(r#"Bearer realm="api""#.to_string()),
+                }
+            }
+
+            async fn on_roles(&self, _roles: &[String]) -> Result<(), Response> {
+                Ok(())
+            }
+
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                match ctx.headers().get("authorization") {
+                    Some(token) if token == "Bearer admin" => Ok(()),
+                    _ => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(TokenGuard))
+            .roles(&["admin".to_string()])
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()["www-authenticate"],
+            r#"Bearer realm="api""#
+        );
+
+        let response = client
+            .get("/test")
+            .header("authorization", "Bearer guest")
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers().get("www-authenticate").is_none());
+        let status = client
+            .get("/test")
+            .header("authorization", "Bearer admin")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_server_timing() {
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .roles(&["admin".to_string()])
+            .server_timing()
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let value = response.headers()["server-timing"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let phases = value
+            .split(", ")
+            .map(|metric| {
+                let (name, dur) = metric.split_once(";dur=").unwrap();
+                assert!(dur.parse::<f64>().is_ok());
+                name
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(phases, vec!["guard", "roles"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_request_header() {
+        #[derive(Clone)]
+        struct HeaderGuard;
+        impl OnGuard for HeaderGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                assert_eq!(ctx.method(), Method::GET);
+                assert_eq!(ctx.uri().path(), "/test");
+                match ctx.headers().get("authorization") {
+                    Some(value) if value == "Bearer admin" => Ok(()),
+                    _ => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::<HeaderGuard, ()>::new("my:test", Arc::new(HeaderGuard))
+            .action("action1", "/test", get(handler))
+            .build();
+        let app = Router::new().nest("/api", router);
+
+        let client = TestClient::new(app);
+        let status = client.get("/api/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = client
+            .get("/api/test")
+            .header("authorization", "Bearer admin")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_request_cookie() {
+        #[derive(Clone)]
+        struct CookieGuard;
+        impl OnGuard for CookieGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                match ctx.cookie("session") {
+                    Some("admin") => Ok(()),
+                    _ => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(CookieGuard))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let status = client
+            .get("/test")
+            .header("cookie", "theme=dark; session=admin")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+
+        let status = client
+            .get("/test")
+            .header("cookie", "admin_session=admin")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_request_query() {
+        #[derive(Clone)]
+        struct TenantGuard;
+        impl OnGuard for TenantGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                match ctx.query().get("tenant").map(String::as_str) {
+                    Some("acme") => Ok(()),
+                    _ => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(TenantGuard))
+            .action("action1", "/list", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let status = client.get("/list?page=2&tenant=acme").await.status();
+        assert_eq!(status, StatusCode::OK);
+        let status = client.get("/list?tenant=other").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client.get("/list").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_request_extensions() {
+        #[derive(Clone)]
+        struct CurrentUser(&'static str);
+
+        #[derive(Clone)]
+        struct ExtensionGuard;
+        impl OnGuard for ExtensionGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                match ctx.extensions().get::<CurrentUser>() {
+                    Some(CurrentUser("admin")) => Ok(()),
+                    _ => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let app = |user| {
+            GuardRouter::new("my:test", Arc::new(ExtensionGuard))
+                .action("action1", "/test", get(handler))
+                .build()
+                .layer(Extension(CurrentUser(user)))
+        };
+        let status = TestClient::new(app("admin")).get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+        let status = TestClient::new(app("guest")).get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_roles_handle() {
+        let router = GuardRouter::<UserGuard, ()>::new("my:test", Arc::new(UserGuard))
+            .roles(&["admin".to_string()]);
+        let roles = router.roles_handle();
+        let router = router.action("action1", "/test", get(handler)).build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        roles.set(&["user".to_string()]);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(roles.get(), Some(vec!["user".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_guard_with_request_id() {
+        #[derive(Clone, Default)]
+        struct AuditGuard(Arc<Mutex<Vec<String>>>);
+        impl OnGuard for AuditGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                let id = ctx.request_id().unwrap();
+                self.0.lock().unwrap().push(format!("request:{id}"));
+                Ok(())
+            }
+
+            async fn on_audit(
+                &self,
+                _resource: &str,
+                _action: &str,
+                _status: StatusCode,
+                _elapsed: Duration,
+                request_id: Option<&str>,
+            ) {
+                let id = request_id.unwrap();
+                self.0.lock().unwrap().push(format!("audit:{id}"));
+            }
+        }
+
+        let guard = AuditGuard::default();
+        let router = GuardRouter::<AuditGuard, ()>::new("my:test", Arc::new(guard.clone()))
+            .with_request_id(HeaderName::from_static("x-request-id"))
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(!id.is_empty());
+
+        let response = client.get("/test").header("x-request-id", "req-1").await;
+        assert_eq!(response.headers()["x-request-id"], "req-1");
+        assert_eq!(
+            *guard.0.lock().unwrap(),
+            vec![
+                format!("request:{id}"),
+                format!("audit:{id}"),
+                "request:req-1".to_string(),
+                "audit:req-1".to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_guard_params() {
+        #[derive(Clone, Default)]
+        struct ParamsGuard(Arc<Mutex<Vec<BTreeMap<String, String>>>>);
+        impl OnGuard for ParamsGuard {
+            async fn on_guard_params(
+                &self,
+                _resource: &str,
+                _action: &str,
+                params: &BTreeMap<String, String>,
+            ) -> Result<(), Response> {
+                self.0.lock().unwrap().push(params.clone());
+                Ok(())
+            }
+        }
+
+        let guard = ParamsGuard::default();
+        let router = GuardRouter::<ParamsGuard, ()>::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/user/:id", get(handler))
+            .action("action2", "/test", get(handler))
+            .build();
+        let app = Router::new().nest("/api", router);
+
+        let client = TestClient::new(app);
+        let status = client.get("/api/user/42").await.status();
+        assert_eq!(status, StatusCode::OK);
+        let status = client.get("/api/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+
+        let params = guard.0.lock().unwrap().clone();
+        assert_eq!(
+            params,
+            vec![
+                BTreeMap::from([("id".to_string(), "42".to_string())]),
+                BTreeMap::new()
+            ]
+        );
+    }
+
+    #[derive(Clone)]
+    struct UserGuard;
+
+    impl OnGuard for UserGuard {
+        // the caller only holds the user role
+        async fn on_roles(&self, roles: &[String]) -> Result<(), Response> {
+            match roles.iter().any(|role| role == "user") {
+                true => Ok(()),
+                false => Err(StatusCode::FORBIDDEN.into_response()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_with_roles() {
+        let admin = vec!["admin".to_string()];
+        let user = vec!["user".to_string()];
+        let router = GuardRouter::<UserGuard, ()>::new("my:test", Arc::new(UserGuard))
+            .roles(&admin)
+            .action_with_roles("action1", "/user", &user, get(handler))
+            .action("action2", "/admin", get(handler))
+            .route(
+                "/test",
+                action::get_with_roles("action3", &user, handler)
+                    .post_with_roles("action4", &admin, handler2),
+            )
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client.get("/user").await.status();
+        assert_eq!(status, StatusCode::OK);
+        let status = client.get("/admin").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::OK);
+        let status = client.post("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_roles() {
+        let admin = vec!["admin".to_string()];
+        let user = vec!["user".to_string()];
+        let router = GuardRouter::<UserGuard, ()>::new("my:test", Arc::new(UserGuard))
+            .route(
+                "/test",
+                action::delete("action1", handler)
+                    .roles(&admin)
+                    .get("action2", handler)
+                    .roles(&user),
+            )
+            .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.delete("/test").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_csp() {
+        async fn page() -> Html<&'static str> {
+            Html("<h1>page</h1>")
+        }
+        async fn data() -> Json<Vec<u32>> {
+            Json(vec![1, 2])
+        }
+
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .route(
+                "/page",
+                action::get("action1", page).csp("default-src 'self'"),
+            )
+            .route(
+                "/data",
+                action::get("action2", data).csp("default-src 'none'"),
             )
             .build();
 
-        let app = Router::new().nest("/api", router);
-        let client = TestClient::new(app);
-        let status = client.get("/api/test").await.status();
-        assert_eq!(status, StatusCode::FORBIDDEN);
+        let client = TestClient::new(router);
+        let response = client.get("/page").await;
+        assert_eq!(
+            response.headers()["content-security-policy"],
+            "default-src 'self'"
+        );
+
+        let response = client.get("/data").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("content-security-policy"));
+    }
+
+    #[tokio::test]
+    async fn test_guard_info_extension() {
+        async fn info_handler(Extension(info): Extension<GuardInfo>) -> String {
+            format!("{}:{}:{:?}", info.resource, info.action, info.roles)
+        }
+
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .roles(&["admin".to_string()])
+            .action("action1", "/test", get(info_handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let text = client.get("/test").await.text().await;
+        assert_eq!(text, r#"my:test:action1:Some(["admin"])"#);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_skips_handler() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let handler = move || async move {
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            format!("count={count}")
+        };
+
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .response_cache(Duration::from_secs(60), |parts| {
+                Some(parts.uri.path().to_string())
+            })
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let text = client.get("/test").await.text().await;
+        assert_eq!(text, "count=1");
+        let text = client.get("/test").await.text().await;
+        assert_eq!(text, "count=1");
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            guard.calls(),
+            vec!["guard:my:test:action1", "guard:my:test:action1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_merge() {
+        let guard = TestGuard::new_with(true, true);
+        let read = GuardRouter::new("my:test", Arc::new(guard.clone())).action(
+            "action1",
+            "/test",
+            get(handler),
+        );
+        let write = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action2", "/test", post(handler2))
+            .action("action3", "/test2", get(handler));
+        let router = read.merge(write).build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test2").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:action1",
+                "guard:my:test:action2",
+                "guard:my:test:action3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_merge_roles() {
+        let read = GuardRouter::new("my:test", Arc::new(UserGuard)).action(
+            "action1",
+            "/test",
+            get(handler),
+        );
+        let write = GuardRouter::new("my:test", Arc::new(UserGuard))
+            .roles(&["admin".to_string()])
+            .action("action2", "/test", post(handler2));
+        let router = read.merge(write).build();
+
+        // the caller without the admin role of the merged router is denied
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/test").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot merge guard routers with a different on_deny option")]
+    fn test_guard_merge_different_options() {
+        let guard = Arc::new(TestGuard::new());
+        let read = GuardRouter::<TestGuard, ()>::new("my:test", guard.clone())
+            .on_deny(|reason: DenyReason| reason.response)
+            .action("action1", "/test", get(handler));
+        let write = GuardRouter::<TestGuard, ()>::new("my:test", guard)
+            .on_deny(|reason: DenyReason| reason.response)
+            .action("action2", "/test", post(handler2));
+        let _ = read.merge(write);
+    }
+
+    #[tokio::test]
+    async fn test_guard_layer() {
+        async fn tag(mut response: Response) -> Response {
+            let value = HeaderValue::from_static("1");
+            response.headers_mut().insert("x-layer", value);
+            response
+        }
+
+        let guard = TestGuard::new_with(true, false);
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .layer(axum::middleware::map_response(tag))
+            .action("action1", "/test", get(handler))
+            .action_with_roles("action2", "/admin", &["admin".to_string()], get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-layer"], "1");
+        // the layer wraps the guard
+        let response = client.get("/admin").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["x-layer"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_guard_route_layer() {
+        async fn count(
+            State(calls): State<Arc<AtomicUsize>>,
+            request: axum::extract::Request,
+            next: axum::middleware::Next,
+        ) -> Response {
+            calls.fetch_add(1, Ordering::SeqCst);
+            next.run(request).await
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let guard = TestGuard::new_with(true, false);
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .route_layer(axum::middleware::from_fn_with_state(calls.clone(), count))
+            .action("action1", "/test", get(handler))
+            .action_with_roles("action2", "/admin", &["admin".to_string()], get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/admin").await.status(), StatusCode::FORBIDDEN);
+        // the denied request does not reach the route layer
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
 
-        let status = client.post("/api/test").await.status();
-        assert_eq!(status, StatusCode::FORBIDDEN);
+    #[tokio::test]
+    async fn test_guard_router_into() {
+        let guard = TestGuard::new_with(true, true);
+        let user = GuardRouter::new("my:test", Arc::new(guard.clone())).action(
+            "action1",
+            "/test",
+            get(handler),
+        );
+        let router = Router::new().nest("/user", user.into());
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/user/test").await.status(), StatusCode::OK);
+        assert_eq!(guard.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot merge guard routers with the same route POST /test")]
+    fn test_guard_merge_same_route() {
+        let guard = Arc::new(TestGuard::new());
+        let read = GuardRouter::<TestGuard, ()>::new("my:test", guard.clone()).route(
+            "/test",
+            action::get("action1", handler).post("action2", handler2),
+        );
+        let write = GuardRouter::<TestGuard, ()>::new("my:test", guard)
+            .route("/test", action::post("action3", handler2));
+        let _ = read.merge(write);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot merge guard routers with different resources")]
+    fn test_guard_merge_different_resources() {
+        let guard = Arc::new(TestGuard::new());
+        let read = GuardRouter::<TestGuard, ()>::new("my:read", guard.clone()).action(
+            "action1",
+            "/test",
+            get(handler),
+        );
+        let write = GuardRouter::<TestGuard, ()>::new("my:write", guard).action(
+            "action2",
+            "/test",
+            post(handler2),
+        );
+        let _ = read.merge(write);
     }
 
     #[tokio::test]
-    async fn test_guard_route_pass() {
+    async fn test_guard_actions_same_path() {
         let guid = Arc::new(TestGuard::new_with(true, true));
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
-            .route(
-                "/test",
-                action::get("action1", handler).post("action2", handler2),
-            )
+        let router = GuardRouter::new("my:test", guid)
+            .action("read", "/item", get(handler))
+            .action("write", "/item", post(handler2))
             .build();
 
-        let app = Router::new().nest("/api", router);
-        let client = TestClient::new(app);
-        let status = client.get("/api/test").await.status();
-        assert_eq!(status, StatusCode::OK);
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/item").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/item").await.status(), StatusCode::OK);
+    }
 
-        let status = client.post("/api/test").await.status();
-        assert_eq!(status, StatusCode::OK);
+    #[test]
+    #[should_panic(expected = "Overlapping method route")]
+    fn test_guard_actions_same_method_panic() {
+        let guid = Arc::new(TestGuard::new());
+        let _ = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+            .action("create", "/item", post(handler))
+            .action("update", "/item", post(handler2))
+            .build();
     }
 
     #[tokio::test]
-    async fn test_guard_guard_pass() {
+    async fn test_guard_action_with_decision() {
         let guid = Arc::new(TestGuard::new_with(true, true));
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+        let router = GuardRouter::new("my:test", guid)
+            .action_with_decision(
+                "action1",
+                "/test/:id",
+                MethodFilter::GET,
+                |decision: GuardInfo, Path(id): Path<String>| async move {
+                    format!("{id}:{}", decision.action)
+                },
+            )
+            .build();
+
+        let client = TestClient::new(router);
+        let text = client.get("/test/1").await.text().await;
+        assert_eq!(text, "1:action1");
+    }
+
+    #[tokio::test]
+    async fn test_guard_roles_any_all() {
+        let roles = ["admin".to_string(), "editor".to_string()];
+        let any = TestGuard::new_with(true, true);
+        let all = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(any.clone()))
+            .roles_any(&roles)
+            .action("action1", "/any", get(handler))
+            .build()
+            .merge(
+                GuardRouter::new("my:test", Arc::new(all.clone()))
+                    .roles_all(&roles)
+                    .action("action2", "/all", get(handler))
+                    .build(),
+            );
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/any").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/all").await.status(), StatusCode::OK);
+        assert_eq!(
+            any.calls(),
+            vec!["roles:admin,editor", "guard:my:test:action1"]
+        );
+        assert_eq!(
+            all.calls(),
+            vec!["roles:admin", "roles:editor", "guard:my:test:action2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_roles_for() {
+        #[derive(Clone, Default)]
+        struct EndpointRoles(Arc<Mutex<Vec<String>>>);
+
+        impl OnGuard for EndpointRoles {
+            async fn on_roles_for(
+                &self,
+                resource: &str,
+                action: &str,
+                roles: &[String],
+            ) -> Result<(), Response> {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{resource}:{action}:{}", roles.join(",")));
+                match action {
+                    "action1" => Ok(()),
+                    _ => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let guard = EndpointRoles::default();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .roles(&["admin".to_string()])
             .action("action1", "/test", get(handler))
+            .action("action2", "/test2", get(handler))
             .build();
 
         let client = TestClient::new(router);
-        let status = client.get("/test").await.status();
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test2").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            *guard.0.lock().unwrap(),
+            vec!["my:test:action1:admin", "my:test:action2:admin"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_roles_for_request() {
+        #[derive(Clone)]
+        struct HeaderRolesGuard;
+
+        impl OnGuard for HeaderRolesGuard {
+            async fn on_roles_for_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                let roles = ctx
+                    .headers()
+                    .get("x-roles")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default();
+                match roles.split(',').any(|role| role == "user") {
+                    true => Ok(()),
+                    false => Err(StatusCode::FORBIDDEN.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(HeaderRolesGuard))
+            .action("action1", "/test", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        let status = client
+            .get("/test")
+            .header("x-roles", "admin,user")
+            .await
+            .status();
         assert_eq!(status, StatusCode::OK);
+
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn test_guard_guard_nest() {
+    async fn test_guard_action_max_concurrent() {
         let guid = Arc::new(TestGuard::new_with(true, true));
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
+        let router = GuardRouter::new("my:test", guid);
+        check_max_concurrent(router).await;
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_max_concurrent_off() {
+        let config = Arc::new(GuardConfig::new());
+        config.set("my:test", Enforcement::Off);
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new())).config(config);
+        check_max_concurrent(router).await;
+    }
+
+    async fn check_max_concurrent(router: GuardRouter<TestGuard, ()>) {
+        let entered = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let slow = {
+            let (entered, release) = (entered.clone(), release.clone());
+            move || async move {
+                entered.notify_one();
+                release.notified().await;
+            }
+        };
+
+        let router = router
+            .action_max_concurrent("action1", "/slow", get(slow), 1)
+            .action("action2", "/test", get(handler))
+            .build();
+
+        let client = Arc::new(TestClient::new(router));
+        let first = tokio::spawn({
+            let client = client.clone();
+            async move { client.get("/slow").await.status() }
+        });
+        entered.notified().await;
+
+        let status = client.get("/slow").await.status();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap(), StatusCode::OK);
+        release.notify_one();
+        assert_eq!(client.get("/slow").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_response() {
+        #[derive(Clone, Default)]
+        struct ResponseGuard(Arc<Mutex<Vec<(String, StatusCode)>>>);
+
+        impl OnGuard for ResponseGuard {
+            async fn on_response(
+                &self,
+                _resource: &str,
+                action: &str,
+                response: &Parts,
+                _request_id: Option<&str>,
+            ) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((action.to_string(), response.status));
+            }
+        }
+
+        let guard = ResponseGuard::default();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
             .action("action1", "/test", get(handler))
+            .action(
+                "action2",
+                "/missing",
+                get(|| async { StatusCode::NOT_FOUND }),
+            )
             .build();
-        let app = Router::new().nest("/api", router);
-        let client = TestClient::new(app);
-        let status = client.get("/api/test").await.status();
-        assert_eq!(status, StatusCode::OK);
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/missing").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            *guard.0.lock().unwrap(),
+            vec![
+                ("action1".to_string(), StatusCode::OK),
+                ("action2".to_string(), StatusCode::NOT_FOUND)
+            ]
+        );
     }
 
     #[tokio::test]
-    async fn test_guard_guard_on_roles_403() {
-        let guid = Arc::new(TestGuard::new_with(true, false));
-        let roles = vec!["admin".to_string()];
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
-            .roles(&roles)
+    async fn test_guard_on_audit() {
+        #[derive(Clone, Default)]
+        struct AuditGuard(Arc<Mutex<Vec<(String, StatusCode, Duration)>>>);
+
+        impl OnGuard for AuditGuard {
+            async fn on_guard(&self, _resource: &str, action: &str) -> Result<(), Response> {
+                match action {
+                    "action2" => Err(StatusCode::FORBIDDEN.into_response()),
+                    _ => Ok(()),
+                }
+            }
+
+            async fn on_audit(
+                &self,
+                _resource: &str,
+                action: &str,
+                status: StatusCode,
+                elapsed: Duration,
+                _request_id: Option<&str>,
+            ) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((action.to_string(), status, elapsed));
+            }
+        }
+
+        let guard = AuditGuard::default();
+        let slow = || async { tokio::time::sleep(Duration::from_millis(20)).await };
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(slow))
+            .action("action2", "/denied", get(handler))
+            .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/denied").await.status(), StatusCode::FORBIDDEN);
+        let records = guard.0.lock().unwrap().clone();
+        let statuses: Vec<_> = records
+            .iter()
+            .map(|(action, status, _)| (action.as_str(), *status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("action1", StatusCode::OK),
+                ("action2", StatusCode::FORBIDDEN)
+            ]
+        );
+        assert!(records[0].2 >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_allowed() {
+        #[derive(Clone, Default)]
+        struct MeterGuard(Arc<Mutex<Vec<String>>>);
+
+        impl OnGuard for MeterGuard {
+            async fn on_guard(&self, _resource: &str, action: &str) -> Result<(), Response> {
+                match action {
+                    "action3" => Err(StatusCode::FORBIDDEN.into_response()),
+                    _ => Ok(()),
+                }
+            }
+
+            async fn on_allowed(
+                &self,
+                resource: &str,
+                action: &str,
+                roles: &[String],
+                _request_id: Option<&str>,
+            ) {
+                let call = format!("{resource}:{action}:{}", roles.join(","));
+                self.0.lock().unwrap().push(call);
+            }
+        }
+
+        let guard = MeterGuard::default();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
             .action("action1", "/test", get(handler))
+            .action_with_roles("action2", "/admin", &["admin".to_string()], get(handler))
+            .action("action3", "/denied", get(handler))
             .build();
 
         let client = TestClient::new(router);
-        let status = client.get("/test").await.status();
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/admin").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/denied").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            *guard.0.lock().unwrap(),
+            vec!["my:test:action1:", "my:test:action2:admin"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_buffer_body() {
+        #[derive(Clone)]
+        struct RoleFieldGuard;
+
+        impl OnGuard for RoleFieldGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                let body = ctx.body().map(|body| String::from_utf8_lossy(body));
+                let admin = ctx.headers().contains_key("x-admin");
+                match body {
+                    Some(body) if body.contains("\"role\"") && !admin => {
+                        Err(StatusCode::FORBIDDEN.into_response())
+                    }
+                    Some(_) => Ok(()),
+                    None => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+                }
+            }
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(RoleFieldGuard))
+            .buffer_body(64)
+            .action("action1", "/user", post(|body: String| async move { body }))
+            .build();
+        let client = TestClient::new(router);
+        let post = |body: &'static str| client.post("/user").body(body);
+
+        let response = post(r#"{"name":"bob"}"#).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, r#"{"name":"bob"}"#);
+        let status = post(r#"{"role":"admin"}"#).await.status();
         assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = post(r#"{"role":"admin"}"#)
+            .header("x-admin", "1")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+        let status = client.post("/user").body("x".repeat(65)).await.status();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
     }
 
     #[tokio::test]
-    async fn test_guard_on_guard_403() {
-        let guid = Arc::new(TestGuard::new_with(false, true));
-        let router = GuardRouter::<TestGuard, ()>::new("my:test", guid)
-            .action("action1", "/test", get(handler))
+    async fn test_guard_filter_response() {
+        #[derive(Clone)]
+        struct Admin(bool);
+
+        #[derive(Clone)]
+        struct RedactGuard;
+
+        impl OnGuard for RedactGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                ctx.insert(Admin(ctx.headers().contains_key("x-admin")));
+                Ok(())
+            }
+
+            async fn on_response_body(
+                &self,
+                _resource: &str,
+                _action: &str,
+                request: &Extensions,
+                _response: &Parts,
+                body: Bytes,
+            ) -> Bytes {
+                if request.get::<Admin>().is_some_and(|admin| admin.0) {
+                    return body;
+                }
+                let mut user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                user.as_object_mut().unwrap().remove("email");
+                serde_json::to_vec(&user).unwrap().into()
+            }
+        }
+
+        let user =
+            || async { Json(serde_json::json!({"name": "bob", "email": "bob@example.com"})) };
+        let router = GuardRouter::new("my:test", Arc::new(RedactGuard))
+            .filter_response(64)
+            .action("action1", "/user", get(user))
+            .action("action2", "/large", get(|| async { "x".repeat(65) }))
+            .build();
+        let client = TestClient::new(router);
+
+        let response = client.get("/user").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, r#"{"name":"bob"}"#);
+        let response = client.get("/user").header("x-admin", "1").await;
+        assert_eq!(
+            response.text().await,
+            r#"{"email":"bob@example.com","name":"bob"}"#
+        );
+        // a body over the limit is not sent unfiltered
+        let status = client.get("/large").await.status();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[cfg(feature = "timeout")]
+    #[tokio::test]
+    async fn test_guard_timeout() {
+        #[derive(Clone)]
+        struct SlowGuard;
+
+        impl OnGuard for SlowGuard {
+            async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            }
+        }
+
+        let closed = GuardRouter::new("my:test", Arc::new(SlowGuard))
+            .guard_timeout(Duration::from_millis(50))
+            .on_deny(|reason: DenyReason| {
+                let mut response = reason.response;
+                let kind = HeaderValue::from_static(reason.kind.as_str());
+                response.headers_mut().insert("x-deny-kind", kind);
+                response
+            })
+            .action("action1", "/closed", get(handler));
+        let open = GuardRouter::new("my:test", Arc::new(SlowGuard))
+            .guard_timeout(Duration::from_millis(50))
+            .on_failure(FailMode::Open)
+            .action("action2", "/open", get(handler));
+        let client = TestClient::new(closed.build().merge(open.build()));
+
+        let response = client.get("/closed").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["x-deny-kind"], "resource");
+        assert_eq!(client.get("/open").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_failure() {
+        #[derive(Clone, Default)]
+        struct BackendGuard(Arc<Mutex<Vec<String>>>);
+
+        impl OnGuard for BackendGuard {
+            async fn on_guard_decision(&self, _ctx: &GuardContext<'_>) -> Decision {
+                Decision::Error("policy service timed out".to_string())
+            }
+
+            async fn on_error(&self, _resource: &str, action: &str, error: &str) {
+                self.0.lock().unwrap().push(format!("{action}:{error}"));
+            }
+        }
+
+        let guard = BackendGuard::default();
+        let closed = GuardRouter::new("my:test", Arc::new(guard.clone())).action(
+            "action1",
+            "/closed",
+            get(handler),
+        );
+        let open = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .on_failure(FailMode::Open)
+            .action("action2", "/open", get(handler));
+        let client = TestClient::new(closed.build().merge(open.build()));
+
+        let status = client.get("/closed").await.status();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(client.get("/open").await.status(), StatusCode::OK);
+        assert_eq!(
+            *guard.0.lock().unwrap(),
+            vec![
+                "action1:policy service timed out",
+                "action2:policy service timed out"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_query_limits() {
+        #[derive(Clone)]
+        struct LimitsGuard;
+
+        impl OnGuard for LimitsGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                let admin = ctx
+                    .headers()
+                    .get("x-user")
+                    .is_some_and(|user| user == "admin");
+                ctx.insert(QueryLimits::new(if admin { 100 } else { 10 }));
+                Ok(())
+            }
+        }
+
+        async fn list(Extension(limits): Extension<QueryLimits>) -> String {
+            limits.page_size(Some(50)).to_string()
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(LimitsGuard))
+            .action("action1", "/list", get(list))
+            .build();
+
+        let client = TestClient::new(router);
+        let text = client.get("/list").await.text().await;
+        assert_eq!(text, "10");
+        let text = client
+            .get("/list")
+            .header("x-user", "admin")
+            .await
+            .text()
+            .await;
+        assert_eq!(text, "50");
+    }
+
+    #[tokio::test]
+    async fn test_guard_with_state() {
+        #[derive(Clone)]
+        struct AppState {
+            name: String,
+        }
+
+        async fn state_handler(State(state): State<AppState>) -> String {
+            state.name
+        }
+
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router: Router = GuardRouter::new("my:test", guid.clone())
+            .action("action1", "/test", get(state_handler))
+            .with_state(AppState {
+                name: "app".to_string(),
+            });
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.text().await, "app");
+
+        let router = GuardRouter::new("my:test", guid.clone())
+            .action("action1", "/test", get(state_handler))
+            .into_router()
+            .with_state(AppState {
+                name: "moved".to_string(),
+            });
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.text().await, "moved");
+
+        let router = GuardRouter::new("my:test", guid)
+            .action("action1", "/test", get(state_handler))
+            .with_state(AppState {
+                name: "with".to_string(),
+            });
+        let client = TestClient::new(Router::new().nest("/app", router));
+        assert_eq!(client.get("/app/test").await.text().await, "with");
+    }
+
+    #[derive(Clone)]
+    struct QuotaState {
+        used: Arc<AtomicUsize>,
+        quota: usize,
+    }
+
+    #[derive(Clone)]
+    struct QuotaGuard;
+
+    impl OnGuard for QuotaGuard {
+        async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+            let state = ctx.router_state::<QuotaState>().unwrap();
+            match state.used.fetch_add(1, Ordering::SeqCst) < state.quota {
+                true => Ok(()),
+                false => Err(StatusCode::TOO_MANY_REQUESTS.into_response()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_state() {
+        async fn used(State(state): State<QuotaState>) -> String {
+            state.used.load(Ordering::SeqCst).to_string()
+        }
+
+        let state = QuotaState {
+            used: Arc::new(AtomicUsize::new(0)),
+            quota: 2,
+        };
+        let router = GuardRouter::new("my:test", Arc::new(QuotaGuard))
+            .action("action1", "/test", get(used))
+            .with_state(state.clone());
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.text().await, "1");
+        assert_eq!(client.get("/test").await.text().await, "2");
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(state.used.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_guard_router_state_outer() {
+        #[derive(Clone)]
+        struct StateGuard(Arc<Mutex<Vec<bool>>>);
+
+        impl OnGuard for StateGuard {
+            async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+                let state = ctx.router_state::<QuotaState>();
+                self.0.lock().unwrap().push(state.is_some());
+                Ok(())
+            }
+        }
+
+        async fn quota(State(state): State<QuotaState>) -> String {
+            state.quota.to_string()
+        }
+
+        let state = QuotaState {
+            used: Arc::new(AtomicUsize::new(0)),
+            quota: 2,
+        };
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let guard = Arc::new(StateGuard(seen.clone()));
+        let inner = GuardRouter::new("my:test", guard.clone())
+            .action("action1", "/test", get(quota))
+            .with_state(state.clone());
+        // the outer router gives the state to the handler only
+        let outer = GuardRouter::new("my:test", guard)
+            .action("action1", "/test", get(quota))
+            .build();
+        let router = Router::new()
+            .nest("/outer", outer)
+            .with_state(state)
+            .nest("/inner", inner);
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/inner/test").await.text().await, "2");
+        assert_eq!(client.get("/outer/test").await.text().await, "2");
+        assert_eq!(*seen.lock().unwrap(), vec![true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_typed_names() {
+        enum Resource {
+            User,
+        }
+
+        impl AsRef<str> for Resource {
+            fn as_ref(&self) -> &str {
+                match self {
+                    Resource::User => "my:user",
+                }
+            }
+        }
+
+        enum UserAction {
+            Get,
+            Create,
+            Update,
+        }
+
+        impl AsRef<str> for UserAction {
+            fn as_ref(&self) -> &str {
+                match self {
+                    UserAction::Get => "my:get",
+                    UserAction::Create => "my:create",
+                    UserAction::Update => "my:update",
+                }
+            }
+        }
+
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new(Resource::User, Arc::new(guard.clone()))
+            .action(UserAction::Get, "/user", get(handler))
+            .route(
+                "/users",
+                action::post(UserAction::Create, handler).put(UserAction::Update, handler2),
+            )
+            .build()
+            .route(
+                "/layer",
+                get(handler).layer(crate::guard_layer(
+                    Arc::new(guard.clone()),
+                    Resource::User,
+                    UserAction::Get,
+                )),
+            );
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/user").await.status(), StatusCode::OK);
+        assert_eq!(client.post("/users").await.status(), StatusCode::OK);
+        assert_eq!(client.put("/users").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/layer").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:user:my:get",
+                "guard:my:user:my:create",
+                "guard:my:user:my:update",
+                "guard:my:user:my:get"
+            ]
+        );
+        assert_eq!(
+            Policy::new(Resource::User, UserAction::Update).action,
+            "my:update"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guard_route_service_error_500() {
+        let service = service_fn(|_request: axum::extract::Request| async {
+            Err::<Response, _>(std::io::Error::other("disk failure"))
+        });
+        let method_router = axum::routing::MethodRouter::new().get_service(service);
+
+        let guid = Arc::new(TestGuard::new_with(true, true));
+        let router = GuardRouter::new("my:test", guid.clone())
+            .route(
+                "/test",
+                Action::from_method_router("action1", method_router),
+            )
             .build();
 
         let client = TestClient::new(router);
         let status = client.get("/test").await.status();
-        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(guid.calls(), vec!["guard:my:test:action1"]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_action_any() {
+        let guard = TestGuard::new_with(true, true);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .route(
+                "/test",
+                action::any("action1", || async { "any" }).get("action2", || async { "get" }),
+            )
+            .route("/any", action::any("action3", || async { "any" }))
+            .build();
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.text().await, "get");
+        assert_eq!(client.post("/test").await.text().await, "any");
+        assert_eq!(client.get("/any").await.text().await, "any");
+        assert_eq!(client.post("/any").await.text().await, "any");
+        assert_eq!(client.delete("/any").await.text().await, "any");
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "guard:my:test:action2",
+                "guard:my:test:action1",
+                "guard:my:test:action3",
+                "guard:my:test:action3",
+                "guard:my:test:action3"
+            ]
+        );
     }
 
     async fn handler() {}
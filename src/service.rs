@@ -1,25 +1,63 @@
-use crate::OnGuard;
-use axum::{extract::Request, response::Response};
-use futures::future::BoxFuture;
+use crate::{
+    context::GuardCache,
+    guard::{DenyKind, DenyReason, FailMode},
+    handle::GuardSeed,
+    layer::GuardOptions,
+    limit::Permit,
+    policy::{Requirement, RolesHandle},
+    request_id::RequestId,
+    Decision, Enforcement, GuardContext, GuardInfo, GuardSkipped, OnGuard, Outcome, Policy,
+};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{FromRequestParts, RawPathParams, Request},
+    http::{self, header, request::Parts, Extensions, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::{future::BoxFuture, FutureExt};
+use http_body_util::LengthLimitError;
 use std::{
+    any::Any,
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
     sync::Arc,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 use tower::Service;
 
+pub(crate) type SkipFn = dyn Fn(&Parts) -> bool + Send + Sync;
+
+/// The predicate of the requests passed without the guard
+#[derive(Clone)]
+pub(crate) struct SkipIf(pub(crate) Arc<SkipFn>);
+
+impl fmt::Debug for SkipIf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SkipIf").finish_non_exhaustive()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GuardService<G, S> {
     pub(crate) guard: Arc<G>,
     pub(crate) inner: S,
-    pub(crate) resource: String,
-    pub(crate) action: String,
-    pub(crate) roles: Option<Vec<String>>,
+    pub(crate) policy: Policy,
+    pub(crate) roles_handle: Option<RolesHandle>,
+    pub(crate) options: Arc<GuardOptions>,
 }
 
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+const GUARD_RESOURCE: HeaderName = HeaderName::from_static("x-guard-resource");
+const GUARD_ACTION: HeaderName = HeaderName::from_static("x-guard-action");
+const GUARD_REASON: HeaderName = HeaderName::from_static("x-guard-reason");
+
 impl<G, S> Service<Request> for GuardService<G, S>
 where
-    G: OnGuard + Clone,
-    S: Service<Request, Response = Response> + Send + 'static,
+    G: OnGuard + 'static,
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
     S::Future: Send + 'static,
 {
     type Response = S::Response;
@@ -33,31 +71,1030 @@ where
     fn call(&mut self, request: Request) -> Self::Future {
         log::debug!(
             "GuardService: resource={} action={}",
-            self.resource,
-            self.action
+            self.policy.resource,
+            self.policy.action
         );
+        // the readied inner service is moved into the future,
+        // and a fresh clone is left to be readied for the next call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
         let guard = self.guard.clone();
+        let mut policy = self.policy.clone();
+        if let Some(roles) = &self.roles_handle {
+            policy.roles = roles.get();
+        }
+        let options = self.options.clone();
+        // moved into the future, so it reports the cancelled requests too
+        let mut completion = Completion {
+            guard: guard.clone(),
+            resource: policy.resource.clone(),
+            action: policy.action.clone(),
+            outcome: Outcome::Cancelled,
+        };
+        Box::pin(async move {
+            let started = Instant::now();
+            let (mut parts, body) = request.into_parts();
+            let request_id = options.request_id.clone().map(|header| {
+                let id = RequestId::resolve(&mut parts, &header);
+                (header, id)
+            });
+            let id = request_id.as_ref().map(|(_, id)| id.as_str());
 
-        let resource = self.resource.clone();
-        let action = self.action.clone();
-        let roles = self.roles.clone();
-        let result = futures::executor::block_on(async move {
-            if let Some(roles) = &roles {
-                if let Err(ret) = guard.on_roles(roles).await {
-                    return Err(ret);
+            let mut response = async {
+                let mode = options.mode(&policy.resource);
+                let bypassed = options.bypasses(mode, &parts);
+                parts.extensions.insert(GuardSeed {
+                    guard: guard.clone(),
+                    options: options.clone(),
+                    bypassed,
+                });
+                if bypassed {
+                    // the limit of the route holds without the guard too
+                    let _permit = match acquire(&options, &policy) {
+                        Ok(permit) => permit,
+                        Err(status) => return Ok(status.into_response()),
+                    };
+                    return inner.call(Request::from_parts(parts, body)).await;
                 }
-            }
-            guard.on_guard(&resource, &action).await
-        });
 
-        if let Err(ret) = result {
-            return Box::pin(async move { Ok(ret) });
-        }
+                // the buffered body is checked by the guard and then passed on
+                let (body, buffered) = match options.body_limit {
+                    Some(limit) => match to_bytes(body, limit).await {
+                        Ok(bytes) => (Body::from(bytes.clone()), Some(bytes)),
+                        Err(err) => {
+                            log::debug!("GuardService: failed to buffer the body: {err}");
+                            // only a body over the limit is too large
+                            let status = match err.into_inner().is::<LengthLimitError>() {
+                                true => StatusCode::PAYLOAD_TOO_LARGE,
+                                false => StatusCode::BAD_REQUEST,
+                            };
+                            return Ok(status.into_response());
+                        }
+                    },
+                    None => (body, None),
+                };
 
-        let future = self.inner.call(request);
-        Box::pin(async move {
-            let response: Response = future.await?;
+                let params = path_params(&mut parts).await;
+                let mut memo = options
+                    .cached
+                    .then(|| parts.extensions.get::<GuardCache>().cloned())
+                    .map(Option::unwrap_or_default);
+                // the roles only layers and the cached checks are allowed
+                // after the roles, without a decision
+                let decides = !(options.roles_only
+                    || memo
+                        .as_ref()
+                        .is_some_and(|memo| memo.is_allowed(&policy.resource, &policy.action)));
+                let ctx = GuardContext::new(&policy, &parts, params, buffered);
+                let ctx = match decides {
+                    true => ctx,
+                    false => ctx.without_decision(),
+                };
+                let mut durations = (Duration::ZERO, Duration::ZERO);
+                let mut skipped = None;
+                let mut chosen = None;
+                // the check which denied the request, `None` for pending
+                let mut denied = Some(DenyKind::Authentication);
+                let mut decided = "challenge";
+                // a panic of the guard is caught while it is polled
+                let result = AssertUnwindSafe(async {
+                    let now = Instant::now();
+                    if let Err(challenge) = guard.on_authenticate(&ctx).await {
+                        return Decision::Challenge(challenge).into_result();
+                    }
+                    denied = Some(DenyKind::Roles);
+                    decided = "deny";
+                    if let Some(response) = ctx.take_denied() {
+                        return Err(response);
+                    }
+                    // the static roles are checked with `on_roles`,
+                    // otherwise the guard derives them from the request
+                    match &policy.roles {
+                        Some(roles) => {
+                            let checks = match policy.requirement {
+                                Requirement::Any => vec![roles.as_slice()],
+                                Requirement::All => {
+                                    roles.iter().map(std::slice::from_ref).collect()
+                                }
+                            };
+                            for roles in checks {
+                                if memo.as_ref().is_some_and(|memo| memo.has_roles(roles)) {
+                                    continue;
+                                }
+                                guard
+                                    .on_roles_for(&policy.resource, &policy.action, roles)
+                                    .await?;
+                                if let Some(memo) = &mut memo {
+                                    memo.pass_roles(roles);
+                                }
+                            }
+                        }
+                        None => guard.on_roles_for_request(&ctx).await?,
+                    }
+                    durations.0 = now.elapsed();
+
+                    let now = Instant::now();
+                    let decision = if !decides {
+                        Decision::Allow
+                    } else if options.candidates.is_empty() {
+                        let decision = guard.on_guard_decision(&ctx).await;
+                        if let (Some(memo), Decision::Allow | Decision::ForceAllow) =
+                            (&mut memo, &decision)
+                        {
+                            memo.allow(&policy.resource, &policy.action);
+                        }
+                        decision
+                    } else {
+                        let candidates = &options.candidates;
+                        match guard.on_guard_candidates(&ctx, candidates).await {
+                            Ok(index) => match candidates.get(index) {
+                                Some(action) => {
+                                    chosen = Some(action.clone());
+                                    Decision::Allow
+                                }
+                                None => {
+                                    log::error!("GuardService: invalid candidate index={index}");
+                                    Decision::Deny(
+                                        StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                                    )
+                                }
+                            },
+                            Err(response) => Decision::Deny(response),
+                        }
+                    };
+                    let decision = match decision {
+                        Decision::Error(error) => {
+                            fail(&*guard, &policy, options.fail_mode, error).await
+                        }
+                        decision => decision,
+                    };
+                    durations.1 = now.elapsed();
+                    decided = decision.as_str();
+                    denied = matches!(
+                        decision,
+                        Decision::Deny(_) | Decision::Challenge(_) | Decision::Error(_)
+                    )
+                    .then_some(DenyKind::Resource);
+                    if let Decision::Skip = decision {
+                        skipped = Some(GuardSkipped {
+                            resource: policy.resource.clone(),
+                            action: policy.action.clone(),
+                        });
+                    }
+                    decision.into_result()
+                })
+                .catch_unwind();
+                let result = match with_timeout(options.guard_timeout, result).await {
+                    Some(Ok(result)) => result,
+                    None => {
+                        let error = "the guard timed out".to_string();
+                        let decision = fail(&*guard, &policy, options.fail_mode, error).await;
+                        // denied like a guard error of the resource check
+                        decided = decision.as_str();
+                        denied =
+                            matches!(decision, Decision::Error(_)).then_some(DenyKind::Resource);
+                        decision.into_result()
+                    }
+                    Some(Err(panic)) => {
+                        panic_event(&policy, panic_message(&*panic));
+                        let (_, observers) = ctx.into_parts();
+                        observers.notify(&policy.resource, &policy.action, "error");
+                        return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                    }
+                };
+                let (extensions, observers) = ctx.into_parts();
+                if let Some(memo) = memo {
+                    parts.extensions.insert(memo);
+                }
+                if let Some(action) = chosen {
+                    policy.action = action;
+                    completion.action.clone_from(&policy.action);
+                }
+
+                // the guard rewrites the request once it is allowed,
+                // and its error response denies it like the checks
+                let mut request = Request::from_parts(parts, body);
+                let result = match result {
+                    Ok(()) => {
+                        request.extensions_mut().extend(extensions);
+                        request.extensions_mut().insert(GuardInfo::from(&policy));
+                        if let Some(skipped) = &skipped {
+                            request.extensions_mut().insert(skipped.clone());
+                        }
+                        guard.on_request_mut(&mut request).await.inspect_err(|_| {
+                            denied = Some(DenyKind::Resource);
+                            decided = "deny";
+                        })
+                    }
+                    result => result,
+                };
+                observers.notify(&policy.resource, &policy.action, decided);
+
+                match result {
+                    Ok(()) => {
+                        if skipped.is_none() {
+                            let roles = policy.roles.as_deref().unwrap_or_default();
+                            guard
+                                .on_allowed(&policy.resource, &policy.action, roles, id)
+                                .await;
+                        }
+                    }
+                    Err(ret) => {
+                        if let Some(kind) = denied {
+                            deny_event(&policy, kind.as_str(), &ret, mode);
+                        }
+                        if mode != Enforcement::Shadow {
+                            let request_id = request_id.as_ref().map(|(_, id)| id);
+                            return Ok(deny(&*guard, &options, &policy, denied, request_id, ret));
+                        }
+                        log::warn!(
+                            "GuardService: shadow deny resource={} action={} status={}",
+                            policy.resource,
+                            policy.action,
+                            ret.status()
+                        );
+                    }
+                }
+
+                let (parts, body) = request.into_parts();
+                // the filter reads the request extensions after the handler
+                let extensions = options.response_limit.map(|_| parts.extensions.clone());
+
+                // the permit is held until the handler returns the response
+                let _permit = match acquire(&options, &policy) {
+                    Ok(permit) => permit,
+                    Err(status) => return Ok(status.into_response()),
+                };
+
+                // a cache hit skips the handler, the guard has already run
+                let cached = options.cache.as_ref().and_then(|cache| {
+                    let key = cache.key(&policy.action, &parts)?;
+                    Some((cache, key))
+                });
+                let response = match cached {
+                    Some((cache, key)) => match cache.get(&key) {
+                        Some(response) => response,
+                        None => {
+                            let response = inner.call(Request::from_parts(parts, body)).await?;
+                            cache.put(key, response).await
+                        }
+                    },
+                    None => inner.call(Request::from_parts(parts, body)).await?,
+                };
+                let (mut head, body) = response.into_parts();
+                guard
+                    .on_response(&policy.resource, &policy.action, &head, id)
+                    .await;
+                let body =
+                    match filter_response(&*guard, &options, &policy, extensions, &mut head, body)
+                        .await
+                    {
+                        Ok(body) => body,
+                        Err(ret) => return Ok(ret),
+                    };
+                let mut response = Response::from_parts(head, body);
+                if let Some(skipped) = skipped {
+                    response.extensions_mut().insert(skipped);
+                }
+                if options.server_timing {
+                    let (roles, guard) = durations;
+                    let value = format!(
+                        "guard;dur={:.3}, roles;dur={:.3}",
+                        guard.as_secs_f64() * 1000.0,
+                        roles.as_secs_f64() * 1000.0
+                    );
+                    if let Ok(value) = HeaderValue::from_str(&value) {
+                        response.headers_mut().append(SERVER_TIMING, value);
+                    }
+                }
+                if let Some(csp) = options.csp.as_ref().filter(|_| is_html(&response)) {
+                    if let Ok(value) = HeaderValue::from_str(csp) {
+                        response
+                            .headers_mut()
+                            .entry(header::CONTENT_SECURITY_POLICY)
+                            .or_insert(value);
+                    }
+                }
+                Ok(response)
+            }
+            .await
+            .inspect_err(|_| completion.finish(Outcome::Failed))?;
+
+            if let Some((header, id)) = &request_id {
+                if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+                    response.headers_mut().insert(header, value);
+                }
+            }
+            guard
+                .on_audit(
+                    &completion.resource,
+                    &completion.action,
+                    response.status(),
+                    started.elapsed(),
+                    id,
+                )
+                .await;
+            completion.finish(Outcome::Responded(response.status()));
             Ok(response)
         })
     }
 }
+
+impl GuardOptions {
+    /// The enforcement mode of the resource, `Enforce` without a config
+    pub(crate) fn mode(&self, resource: &str) -> Enforcement {
+        self.config
+            .as_ref()
+            .map(|config| config.mode(resource))
+            .unwrap_or_default()
+    }
+
+    /// Whether the request is passed to the inner service without the guard
+    fn bypasses(&self, mode: Enforcement, parts: &Parts) -> bool {
+        mode == Enforcement::Off
+            || (self.skip_preflight && is_preflight(parts))
+            || self
+                .skip_if
+                .as_ref()
+                .is_some_and(|skip_if| (skip_if.0)(parts))
+    }
+}
+
+/// Acquire a permit of the concurrency limit, a 503 when it is reached
+fn acquire(options: &GuardOptions, policy: &Policy) -> Result<Option<Permit>, StatusCode> {
+    match options.limit.as_ref().map(|limit| limit.try_acquire()) {
+        Some(None) => {
+            log::debug!(
+                "GuardService: too many requests resource={} action={}",
+                policy.resource,
+                policy.action
+            );
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        permit => Ok(permit.flatten()),
+    }
+}
+
+/// Build the response of a denied request with the guard and the deny handler
+pub(crate) fn deny<G>(
+    guard: &G,
+    options: &GuardOptions,
+    policy: &Policy,
+    denied: Option<DenyKind>,
+    request_id: Option<&RequestId>,
+    response: Response,
+) -> Response
+where
+    G: OnGuard,
+{
+    // a pending decision is returned as is
+    let Some(kind) = denied else {
+        return response;
+    };
+    let reason = |response| DenyReason {
+        kind,
+        resource: policy.resource.clone(),
+        action: policy.action.clone(),
+        request_id: request_id.map(|id| id.as_str().to_string()),
+        response,
+    };
+    let response = guard.on_denied(reason(response));
+    let mut response = match &options.on_deny {
+        Some(on_deny) => (on_deny.0)(reason(response)),
+        None => response,
+    };
+    if options.debug_headers {
+        insert_debug_headers(&mut response, policy, kind);
+    }
+    response
+}
+
+/// Pass the buffered response body through `OnGuard::on_response_body`
+async fn filter_response<G>(
+    guard: &G,
+    options: &GuardOptions,
+    policy: &Policy,
+    extensions: Option<Extensions>,
+    head: &mut http::response::Parts,
+    body: Body,
+) -> Result<Body, Response>
+where
+    G: OnGuard,
+{
+    let (Some(limit), Some(extensions)) = (options.response_limit, extensions) else {
+        return Ok(body);
+    };
+    match to_bytes(body, limit).await {
+        Ok(bytes) => {
+            let bytes = guard
+                .on_response_body(&policy.resource, &policy.action, &extensions, head, bytes)
+                .await;
+            // the length is set again from the filtered body
+            head.headers.remove(header::CONTENT_LENGTH);
+            Ok(Body::from(bytes))
+        }
+        Err(err) => {
+            log::error!(
+                "GuardService: failed to buffer the response resource={} action={}: {err}",
+                policy.resource,
+                policy.action
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Report a guard error and apply the fail mode
+pub(crate) async fn fail<G>(
+    guard: &G,
+    policy: &Policy,
+    fail_mode: FailMode,
+    error: String,
+) -> Decision
+where
+    G: OnGuard,
+{
+    guard
+        .on_error(&policy.resource, &policy.action, &error)
+        .await;
+    log::warn!(
+        "GuardService: guard error resource={} action={} mode={:?}: {}",
+        policy.resource,
+        policy.action,
+        fail_mode,
+        error
+    );
+    match fail_mode {
+        FailMode::Open => Decision::Allow,
+        FailMode::Closed => Decision::Error(error),
+    }
+}
+
+/// Race the future against the timeout, `None` when it elapses
+async fn with_timeout<F>(timeout: Option<Duration>, future: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    match timeout {
+        #[cfg(feature = "timeout")]
+        Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+        _ => Some(future.await),
+    }
+}
+
+/// A CORS preflight is an `OPTIONS` request with `Access-Control-Request-Method`
+fn is_preflight(parts: &Parts) -> bool {
+    parts.method == Method::OPTIONS
+        && parts
+            .headers
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Call `OnGuard::on_complete` when the request future is dropped
+struct Completion<G: OnGuard> {
+    guard: Arc<G>,
+    resource: String,
+    action: String,
+    outcome: Outcome,
+}
+
+impl<G: OnGuard> Completion<G> {
+    fn finish(&mut self, outcome: Outcome) {
+        self.outcome = outcome;
+    }
+}
+
+impl<G: OnGuard> Drop for Completion<G> {
+    fn drop(&mut self) {
+        self.guard
+            .on_complete(&self.resource, &self.action, self.outcome);
+    }
+}
+
+pub(crate) fn deny_event(policy: &Policy, reason: &str, response: &Response, mode: Enforcement) {
+    let shadow = mode == Enforcement::Shadow;
+    log::debug!(
+        "GuardService: deny resource={} action={} reason={reason} status={} shadow={shadow}",
+        policy.resource,
+        policy.action,
+        response.status()
+    );
+    #[cfg(feature = "tracing")]
+    tracing::event!(
+        tracing::Level::WARN,
+        resource = %policy.resource,
+        action = %policy.action,
+        reason,
+        status = response.status().as_u16(),
+        shadow,
+        "guard denied"
+    );
+}
+
+fn panic_event(policy: &Policy, message: &str) {
+    log::error!(
+        "GuardService: the guard panicked resource={} action={}: {message}",
+        policy.resource,
+        policy.action
+    );
+    #[cfg(feature = "tracing")]
+    tracing::event!(
+        tracing::Level::ERROR,
+        resource = %policy.resource,
+        action = %policy.action,
+        panic = message,
+        "guard panicked"
+    );
+}
+
+fn insert_debug_headers(response: &mut Response, policy: &Policy, kind: DenyKind) {
+    let headers = response.headers_mut();
+    for (name, value) in [
+        (GUARD_RESOURCE, policy.resource.as_str()),
+        (GUARD_ACTION, policy.action.as_str()),
+        (GUARD_REASON, kind.as_str()),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"))
+}
+
+pub(crate) async fn path_params(parts: &mut Parts) -> BTreeMap<String, String> {
+    match RawPathParams::from_request_parts(parts, &()).await {
+        Ok(params) => params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::GuardActionLayer;
+    use crate::test_helper::{TestClient, TestGuard};
+    use crate::{
+        Decision, DenyReason, GuardContext, GuardInfo, GuardRouter, GuardSkipped, OnGuard, Outcome,
+    };
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{HeaderMap, StatusCode},
+        middleware::map_response,
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Extension,
+    };
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+    use tower::ServiceExt;
+
+    #[derive(Clone)]
+    struct SleepGuard;
+
+    impl OnGuard for SleepGuard {
+        async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_does_not_block_runtime() {
+        let router = GuardRouter::new("my:test", Arc::new(SleepGuard))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+
+        // a blocking guard would serialize the requests on the single
+        // test worker thread, or never wake up the sleep at all
+        let now = Instant::now();
+        let requests = (0..8).map(|_| async { client.get("/test").await.status() });
+        let statuses =
+            tokio::time::timeout(Duration::from_secs(5), futures::future::join_all(requests))
+                .await
+                .expect("guard blocked the runtime");
+
+        assert!(statuses.iter().all(|status| *status == StatusCode::OK));
+        assert!(now.elapsed() < Duration::from_millis(8 * 200));
+    }
+
+    #[derive(Clone)]
+    struct SkipGuard;
+
+    impl OnGuard for SkipGuard {
+        async fn on_guard_decision(&self, _ctx: &GuardContext<'_>) -> Decision {
+            Decision::Skip
+        }
+    }
+
+    fn skip_app(outer: TestGuard) -> axum::Router {
+        async fn skipped_handler(Extension(skipped): Extension<GuardSkipped>) -> String {
+            skipped.action
+        }
+
+        GuardRouter::new("my:test", Arc::new(SkipGuard))
+            .action("inner", "/test", get(skipped_handler))
+            .build()
+            .layer(GuardActionLayer::new(Arc::new(outer), "my:test", "outer"))
+            // the outer layer sees the marker in the response
+            .layer(map_response(|mut response: Response| async move {
+                if response.extensions().get::<GuardSkipped>().is_some() {
+                    response
+                        .headers_mut()
+                        .insert("x-guard-skipped", "true".parse().unwrap());
+                }
+                response
+            }))
+    }
+
+    #[tokio::test]
+    async fn test_guard_skip_defers_to_outer() {
+        let client = TestClient::new(skip_app(TestGuard::new_with(true, true)));
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-guard-skipped"], "true");
+        assert_eq!(response.text().await, "inner");
+
+        let client = TestClient::new(skip_app(TestGuard::new()));
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response.headers().contains_key("x-guard-skipped"));
+    }
+
+    #[derive(Clone)]
+    struct PanicGuard;
+
+    impl OnGuard for PanicGuard {
+        async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+            ctx.headers().get("x-user").unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_panic_500() {
+        let router = GuardRouter::new("my:test", Arc::new(PanicGuard))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        // the connection is not reset, and the next request is served too
+        for _ in 0..2 {
+            let status = client.get("/test").await.status();
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    #[derive(Clone)]
+    struct RewriteGuard;
+
+    impl OnGuard for RewriteGuard {
+        async fn on_request_mut(&self, request: &mut Request) -> Result<(), Response> {
+            request.headers_mut().remove("x-internal");
+            request
+                .headers_mut()
+                .insert("x-tenant", "acme".parse().unwrap());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_request_mut() {
+        async fn echo(headers: HeaderMap) -> String {
+            let tenant = headers["x-tenant"].to_str().unwrap();
+            format!("{tenant}:{}", headers.contains_key("x-internal"))
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(RewriteGuard))
+            .action("action1", "/test", get(echo))
+            .build();
+        let client = TestClient::new(router);
+        let response = client.get("/test").header("x-internal", "secret").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await, "acme:false");
+    }
+
+    #[derive(Clone, Default)]
+    struct TenantGuard {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl OnGuard for TenantGuard {
+        async fn on_allowed(
+            &self,
+            _resource: &str,
+            _action: &str,
+            _roles: &[String],
+            _request_id: Option<&str>,
+        ) {
+            self.calls.lock().unwrap().push("allowed".to_string());
+        }
+
+        async fn on_request_mut(&self, request: &mut Request) -> Result<(), Response> {
+            match request.headers().contains_key("x-tenant") {
+                true => Ok(()),
+                false => Err(StatusCode::BAD_REQUEST.into_response()),
+            }
+        }
+
+        fn on_denied(&self, reason: DenyReason) -> Response {
+            let kind = reason.kind.as_str();
+            self.calls.lock().unwrap().push(format!("denied:{kind}"));
+            reason.response
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_request_mut_deny() {
+        let guard = TenantGuard::default();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .debug_headers()
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+
+        // the error response is a deny, without the allow hook
+        let response = client.get("/test").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-guard-reason"], "resource");
+        assert_eq!(*guard.calls.lock().unwrap(), vec!["denied:resource"]);
+
+        let response = client.get("/test").header("x-tenant", "acme").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(guard.calls.lock().unwrap().last().unwrap(), "allowed");
+    }
+
+    #[derive(Clone)]
+    struct TrustedHeadersGuard;
+
+    impl OnGuard for TrustedHeadersGuard {
+        async fn on_request_mut(&self, request: &mut Request) -> Result<(), Response> {
+            let info = request.extensions().get::<GuardInfo>().cloned().unwrap();
+            let headers = request.headers_mut();
+            headers.remove("x-user-id");
+            headers.insert("x-resource", info.resource.parse().unwrap());
+            headers.insert("x-action", info.action.parse().unwrap());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_trusted_headers() {
+        async fn echo(headers: HeaderMap) -> String {
+            format!(
+                "{} {} {}",
+                headers["x-resource"].to_str().unwrap(),
+                headers["x-action"].to_str().unwrap(),
+                headers.contains_key("x-user-id")
+            )
+        }
+
+        let router = GuardRouter::new("my:test", Arc::new(TrustedHeadersGuard))
+            .action("action1", "/test", get(echo))
+            .build();
+        let response = TestClient::new(router)
+            .get("/test")
+            .header("x-user-id", "spoofed")
+            .header("x-action", "spoofed")
+            .await;
+        assert_eq!(response.text().await, "my:test action1 false");
+    }
+
+    #[tokio::test]
+    async fn test_guard_cached_in_request() {
+        let guard = TestGuard::new_with(true, true);
+        let roles = Some(vec!["admin".to_string()]);
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .roles(roles.as_ref().unwrap())
+            .cached(true)
+            .action("action1", "/test", get(handler))
+            .action("action2", "/test2", get(handler))
+            .build()
+            .layer(
+                GuardActionLayer::new(Arc::new(guard.clone()), "my:test", "action1")
+                    .roles(&roles)
+                    .cached(true),
+            );
+
+        let client = TestClient::new(router);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(guard.calls(), vec!["roles:admin", "guard:my:test:action1"]);
+
+        // another action is still checked, and the next request again
+        assert_eq!(client.get("/test2").await.status(), StatusCode::OK);
+        assert_eq!(client.get("/test").await.status(), StatusCode::OK);
+        assert_eq!(
+            guard.calls(),
+            vec![
+                "roles:admin",
+                "guard:my:test:action1",
+                "roles:admin",
+                "guard:my:test:action1",
+                "guard:my:test:action2",
+                "roles:admin",
+                "guard:my:test:action1"
+            ]
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct CompleteGuard(Arc<Mutex<Vec<(String, Outcome)>>>);
+
+    impl OnGuard for CompleteGuard {
+        fn on_complete(&self, _resource: &str, action: &str, outcome: Outcome) {
+            self.0.lock().unwrap().push((action.to_string(), outcome));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guard_buffer_body_error() {
+        let router = GuardRouter::new("my:test", Arc::new(TestGuard::new_with(true, true)))
+            .buffer_body(64)
+            .action("action1", "/test", post(handler))
+            .build();
+
+        // a broken body is not too large
+        let body = futures::stream::iter([Err::<Vec<u8>, _>(io::Error::other("reset"))]);
+        let request = Request::post("/test")
+            .body(Body::from_stream(body))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_guard_on_complete() {
+        async fn slow_handler() {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+
+        let guard = CompleteGuard::default();
+        let router = GuardRouter::new("my:test", Arc::new(guard.clone()))
+            .action("action1", "/test", get(handler))
+            .action("action2", "/slow", get(slow_handler))
+            .build();
+
+        let request = Request::get("/test").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the client goes away before the handler responds
+        let request = Request::get("/slow").body(Body::empty()).unwrap();
+        let result = tokio::time::timeout(Duration::from_millis(50), router.oneshot(request)).await;
+        assert!(result.is_err());
+
+        assert_eq!(
+            *guard.0.lock().unwrap(),
+            vec![
+                ("action1".to_string(), Outcome::Responded(StatusCode::OK)),
+                ("action2".to_string(), Outcome::Cancelled),
+            ]
+        );
+    }
+
+    async fn handler() {}
+
+    #[cfg(feature = "tracing")]
+    mod deny_event {
+        use crate::test_helper::TestGuard;
+        use crate::{GuardRouter, OnGuard};
+        use axum::{body::Body, extract::Request, response::Response, routing::get};
+        use std::{
+            collections::BTreeMap,
+            fmt,
+            sync::{Arc, Mutex},
+        };
+        use tower::ServiceExt;
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Level, Metadata, Subscriber,
+        };
+
+        type Fields = BTreeMap<String, String>;
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<(Level, Fields)>>>);
+
+        struct Visitor<'a>(&'a mut Fields);
+
+        impl Visit for Visitor<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0
+                    .insert(field.name().to_string(), format!("{value:?}"));
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.insert(field.name().to_string(), value.to_string());
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::new();
+                event.record(&mut Visitor(&mut fields));
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((*event.metadata().level(), fields));
+            }
+
+            fn enter(&self, _span: &span::Id) {}
+
+            fn exit(&self, _span: &span::Id) {}
+        }
+
+        #[tokio::test]
+        async fn test_deny_tracing_event() {
+            let capture = Capture::default();
+            let _default = tracing::subscriber::set_default(capture.clone());
+
+            let router = GuardRouter::new("my:test", Arc::new(TestGuard::new_with(false, true)))
+                .action("action1", "/test", get(|| async {}))
+                .build();
+            let request = Request::get("/test").body(Body::empty()).unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), 403);
+
+            let events = capture.0.lock().unwrap().clone();
+            let fields = events
+                .iter()
+                .find(|(level, fields)| {
+                    *level == Level::WARN && fields["message"] == "guard denied"
+                })
+                .map(|(_, fields)| fields.clone())
+                .expect("no deny event");
+            assert_eq!(fields["resource"], "my:test");
+            assert_eq!(fields["action"], "action1");
+            assert_eq!(fields["reason"], "resource");
+            assert_eq!(fields["status"], "403");
+            assert_eq!(fields["shadow"], "false");
+        }
+
+        #[tokio::test]
+        async fn test_panic_tracing_event() {
+            #[derive(Clone)]
+            struct PanicGuard;
+
+            impl OnGuard for PanicGuard {
+                async fn on_guard(&self, _resource: &str, _action: &str) -> Result<(), Response> {
+                    panic!("policy store is gone")
+                }
+            }
+
+            let capture = Capture::default();
+            let _default = tracing::subscriber::set_default(capture.clone());
+
+            let router = GuardRouter::new("my:test", Arc::new(PanicGuard))
+                .action("action1", "/test", get(|| async {}))
+                .build();
+            let request = Request::get("/test").body(Body::empty()).unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), 500);
+
+            let events = capture.0.lock().unwrap().clone();
+            let fields = events
+                .iter()
+                .find(|(level, fields)| {
+                    *level == Level::ERROR && fields["message"] == "guard panicked"
+                })
+                .map(|(_, fields)| fields.clone())
+                .expect("no panic event");
+            assert_eq!(fields["resource"], "my:test");
+            assert_eq!(fields["action"], "action1");
+            assert_eq!(fields["panic"], "policy store is gone");
+        }
+    }
+}
@@ -4,6 +4,7 @@ use axum::response::IntoResponse;
 use axum::serve;
 use futures::future::BoxFuture;
 use reqwest::StatusCode;
+use std::sync::{Arc, Mutex};
 use std::{convert::Infallible, future::IntoFuture, net::SocketAddr};
 use tokio::net::TcpListener;
 use tower::make::Shared;
@@ -15,27 +16,36 @@ use crate::OnGuard;
 pub struct TestGuard {
     pub guard_result: bool,
     pub roles_result: bool,
+    pub calls: Arc<Mutex<Vec<String>>>,
 }
 
 impl TestGuard {
     pub fn new() -> Self {
-        TestGuard {
-            guard_result: false,
-            roles_result: false,
-        }
+        Self::new_with(false, false)
     }
 
     pub fn new_with(guard_result: bool, roles_result: bool) -> Self {
         TestGuard {
             guard_result,
             roles_result,
+            calls: Arc::new(Mutex::new(Vec::new())),
         }
     }
+
+    /// the recorded guard calls, like `roles:admin` or `guard:resource:action`
+    #[allow(dead_code)]
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
 }
 
 impl OnGuard for TestGuard {
     async fn on_guard(&self, resource: &str, action: &str) -> Result<(), axum::response::Response> {
         log::debug!("on_guard: resource={resource},action={action}");
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("guard:{resource}:{action}"));
         match self.guard_result {
             true => Ok(()),
             false => Err((StatusCode::FORBIDDEN, "error").into_response()),
@@ -44,6 +54,10 @@ impl OnGuard for TestGuard {
 
     async fn on_roles(&self, roles: &[String]) -> Result<(), axum::response::Response> {
         log::debug!("on_roles: roles={:?}", roles);
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("roles:{}", roles.join(",")));
         match self.roles_result {
             true => Ok(()),
             false => Err((StatusCode::FORBIDDEN, "error").into_response()),
@@ -111,6 +125,23 @@ impl TestClient {
             builder: self.client.patch(format!("http://{}{}", self.addr, url)),
         }
     }
+
+    #[allow(dead_code)]
+    pub(crate) fn delete(&self, url: &str) -> RequestBuilder {
+        RequestBuilder {
+            builder: self.client.delete(format!("http://{}{}", self.addr, url)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn options(&self, url: &str) -> RequestBuilder {
+        RequestBuilder {
+            builder: self.client.request(
+                reqwest::Method::OPTIONS,
+                format!("http://{}{}", self.addr, url),
+            ),
+        }
+    }
 }
 
 pub(crate) struct RequestBuilder {
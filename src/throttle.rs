@@ -0,0 +1,275 @@
+//! A guard wrapper to block the principals with too many failed attempts
+//!
+//! ```rust,ignore
+//!  use std::{sync::Arc, time::Duration};
+//!  use axum_guard_router::throttle::FailureThrottleGuard;
+//!
+//!  let guard = FailureThrottleGuard::new(MyGuard, |ctx| {
+//!         ctx.headers()
+//!             .get("x-user")
+//!             .and_then(|value| value.to_str().ok())
+//!             .map(|user| user.to_string())
+//!     })
+//!     .threshold(5)
+//!     .cooldown(Duration::from_secs(300));
+//!  let router = GuardRouter::new("my:router:resource", Arc::new(guard))
+//!     .action("my:login", "/login", post(handler));
+//! ```
+use crate::{
+    guard::Decision,
+    timestamp::{Clock, SystemClock},
+    GuardContext, OnGuard,
+};
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+type KeyFn = dyn Fn(&GuardContext<'_>) -> Option<String> + Send + Sync;
+
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    blocked_until: Option<SystemTime>,
+}
+
+/// A guard to throttle the consecutive denials of the inner guard
+///
+/// The denials are counted per key, like the principal or the client ip.
+/// Once the count reaches the threshold, every request of the key is denied
+/// with 429 until the cooldown ends, even when the inner guard would allow
+/// it. An allowed request resets the count. The requests without a key and
+/// the roles checks are not throttled.
+pub struct FailureThrottleGuard<G, C = SystemClock> {
+    inner: G,
+    key: Arc<KeyFn>,
+    threshold: u32,
+    cooldown: Duration,
+    clock: C,
+    failures: Arc<Mutex<HashMap<String, Failures>>>,
+}
+
+impl<G> FailureThrottleGuard<G> {
+    /// Create a guard with the function to resolve the throttle key,
+    /// blocking for 60 seconds after 5 denials
+    pub fn new<F>(inner: G, key: F) -> Self
+    where
+        F: Fn(&GuardContext<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        Self {
+            inner,
+            key: Arc::new(key),
+            threshold: 5,
+            cooldown: Duration::from_secs(60),
+            clock: SystemClock,
+            failures: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<G, C> FailureThrottleGuard<G, C> {
+    /// Set the number of consecutive denials to block the key
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold.max(1);
+        self
+    }
+
+    /// Set how long the key is blocked
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Replace the clock used to check the cooldown
+    pub fn clock<T: Clock>(self, clock: T) -> FailureThrottleGuard<G, T> {
+        FailureThrottleGuard {
+            inner: self.inner,
+            key: self.key,
+            threshold: self.threshold,
+            cooldown: self.cooldown,
+            clock,
+            failures: self.failures,
+        }
+    }
+}
+
+impl<G, C> FailureThrottleGuard<G, C>
+where
+    C: Clock,
+{
+    /// The remaining cooldown of the key, an ended cooldown is reset
+    fn blocked(&self, key: &str) -> Option<Duration> {
+        let now = self.clock.now();
+        let mut failures = self.failures.lock().unwrap();
+        let until = failures.get(key)?.blocked_until?;
+        match until.duration_since(now) {
+            Ok(remaining) if !remaining.is_zero() => Some(remaining),
+            _ => {
+                failures.remove(key);
+                None
+            }
+        }
+    }
+
+    fn record(&self, key: String, decision: &Decision) {
+        let mut failures = self.failures.lock().unwrap();
+        match decision {
+            Decision::Allow | Decision::ForceAllow => {
+                failures.remove(&key);
+            }
+            Decision::Deny(_) => {
+                let entry = failures.entry(key).or_default();
+                entry.count += 1;
+                if entry.count >= self.threshold {
+                    log::debug!(
+                        "FailureThrottleGuard: blocked after {} denials",
+                        entry.count
+                    );
+                    entry.count = 0;
+                    entry.blocked_until = Some(self.clock.now() + self.cooldown);
+                }
+            }
+            // the missing credentials and the guard errors are not failed attempts
+            Decision::Challenge(_) | Decision::Pending(_) | Decision::Skip | Decision::Error(_) => {
+            }
+        }
+    }
+}
+
+impl<G, C> Clone for FailureThrottleGuard<G, C>
+where
+    G: Clone,
+    C: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            key: self.key.clone(),
+            threshold: self.threshold,
+            cooldown: self.cooldown,
+            clock: self.clock.clone(),
+            failures: self.failures.clone(),
+        }
+    }
+}
+
+impl<G, C> OnGuard for FailureThrottleGuard<G, C>
+where
+    G: OnGuard,
+    C: Clock,
+{
+    async fn on_guard_decision(&self, ctx: &GuardContext<'_>) -> Decision {
+        let Some(key) = (self.key)(ctx) else {
+            return self.inner.on_guard_decision(ctx).await;
+        };
+        if let Some(remaining) = self.blocked(&key) {
+            let retry_after = remaining.as_secs().max(1).to_string();
+            return Decision::Deny(
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after)],
+                    "too many failed attempts",
+                )
+                    .into_response(),
+            );
+        }
+
+        let decision = self.inner.on_guard_decision(ctx).await;
+        self.record(key, &decision);
+        decision
+    }
+
+    forward_hooks!(OnGuard, [inner]; checks, observers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailureThrottleGuard;
+    use crate::test_helper::TestClient;
+    use crate::timestamp::Clock;
+    use crate::{GuardContext, GuardRouter, OnGuard};
+    use axum::{
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use reqwest::StatusCode;
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    #[derive(Clone, Default)]
+    struct TestClock(Arc<AtomicU64>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    #[derive(Clone)]
+    struct PasswordGuard;
+
+    impl OnGuard for PasswordGuard {
+        async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+            match ctx.headers().get("x-password") {
+                Some(password) if password == "secret" => Ok(()),
+                _ => Err(StatusCode::FORBIDDEN.into_response()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throttle_blocks_until_cooldown() {
+        let clock = TestClock::default();
+        let guard = FailureThrottleGuard::new(PasswordGuard, |ctx| {
+            ctx.headers()
+                .get("x-user")
+                .and_then(|value| value.to_str().ok())
+                .map(|user| user.to_string())
+        })
+        .threshold(3)
+        .cooldown(Duration::from_secs(60))
+        .clock(clock.clone());
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("action1", "/test", get(handler))
+            .build();
+        let client = TestClient::new(router);
+        let login = |password: &'static str| {
+            client
+                .get("/test")
+                .header("x-user", "alice")
+                .header("x-password", password)
+        };
+
+        for _ in 0..3 {
+            assert_eq!(login("wrong").await.status(), StatusCode::FORBIDDEN);
+        }
+        let response = login("secret").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+
+        // another principal is not blocked
+        let status = client
+            .get("/test")
+            .header("x-user", "bob")
+            .header("x-password", "secret")
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+
+        clock.0.store(61, Ordering::SeqCst);
+        assert_eq!(login("secret").await.status(), StatusCode::OK);
+        assert_eq!(login("wrong").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    async fn handler() {}
+}
@@ -0,0 +1,191 @@
+//! A guard to reject replayed requests by the age of their timestamp header
+//!
+//! ```rust,ignore
+//!  use std::{sync::Arc, time::Duration};
+//!  use axum_guard_router::{guard, timestamp::TimestampGuard};
+//!
+//!  let guard = guard::chain(TimestampGuard::new(Duration::from_secs(300)), MyGuard);
+//!  let router = GuardRouter::new("my:router:resource", Arc::new(guard))
+//!     .action("my:create", "/user", post(handler));
+//! ```
+use crate::{GuardContext, OnGuard};
+use axum::{
+    http::{HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The default header to read the request timestamp from
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+
+/// A clock to get the current time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A guard to check the request timestamp is inside the allowed skew window
+///
+/// The header value is either the unix time in seconds or an http date,
+/// like the `Date` header. A missing, invalid, too old or too far future
+/// timestamp is denied with 401.
+#[derive(Clone, Debug)]
+pub struct TimestampGuard<C = SystemClock> {
+    pub max_skew: Duration,
+    pub header: HeaderName,
+    clock: C,
+}
+
+impl TimestampGuard {
+    /// Create a guard reading the `x-timestamp` header with the system clock
+    pub fn new(max_skew: Duration) -> Self {
+        Self {
+            max_skew,
+            header: HeaderName::from_static(TIMESTAMP_HEADER),
+            clock: SystemClock,
+        }
+    }
+}
+
+impl<C> TimestampGuard<C> {
+    /// Set the header to read the timestamp from
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Replace the clock used to get the current time
+    pub fn clock<T: Clock>(self, clock: T) -> TimestampGuard<T> {
+        TimestampGuard {
+            max_skew: self.max_skew,
+            header: self.header,
+            clock,
+        }
+    }
+}
+
+impl<C> OnGuard for TimestampGuard<C>
+where
+    C: Clock,
+{
+    async fn on_request(&self, ctx: &GuardContext<'_>) -> Result<(), Response> {
+        let timestamp = ctx
+            .headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_timestamp)
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "invalid timestamp").into_response())?;
+
+        let now = self.clock.now();
+        let skew = now
+            .duration_since(timestamp)
+            .or_else(|_| timestamp.duration_since(now))
+            .unwrap_or_default();
+        if skew > self.max_skew {
+            log::debug!(
+                "TimestampGuard: skew={}s is out of max_skew={}s",
+                skew.as_secs(),
+                self.max_skew.as_secs()
+            );
+            return Err((StatusCode::UNAUTHORIZED, "timestamp out of window").into_response());
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<SystemTime> {
+    match value.parse::<u64>() {
+        Ok(secs) => UNIX_EPOCH.checked_add(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, TimestampGuard};
+    use crate::test_helper::TestClient;
+    use crate::GuardRouter;
+    use axum::routing::get;
+    use reqwest::StatusCode;
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[derive(Clone)]
+    struct FixedClock;
+
+    impl Clock for FixedClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(NOW)
+        }
+    }
+
+    fn client() -> TestClient {
+        let guard = TimestampGuard::new(Duration::from_secs(60)).clock(FixedClock);
+        let router = GuardRouter::new("my:test", Arc::new(guard))
+            .action("action1", "/test", get(handler))
+            .build();
+        TestClient::new(router)
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_in_window() {
+        let client = client();
+        let timestamp = (NOW - 30).to_string();
+        let status = client
+            .get("/test")
+            .header("x-timestamp", timestamp)
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+
+        let date = httpdate::fmt_http_date(FixedClock.now());
+        let status = client
+            .get("/test")
+            .header("x-timestamp", date)
+            .await
+            .status();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_too_old_401() {
+        let timestamp = (NOW - 61).to_string();
+        let status = client()
+            .get("/test")
+            .header("x-timestamp", timestamp)
+            .await
+            .status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_future_401() {
+        let client = client();
+        let timestamp = (NOW + 61).to_string();
+        let status = client
+            .get("/test")
+            .header("x-timestamp", timestamp)
+            .await
+            .status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let status = client.get("/test").await.status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn handler() {}
+}